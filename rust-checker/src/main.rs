@@ -1,9 +1,15 @@
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 mod prime_generator;
-mod primes;
 mod time;
 
+use common::args::get_flag;
+use common::checkpoint::Checkpoints;
+use common::factor::{self, Factoring};
+use common::ledger::{Check, Ledger, Record};
+use common::primes::{ErrorChecks, Plain, Test};
+
 const MERSENNE_PRIMES: [u32; 43] = [2, 3, 5, 7, 13, 17, 19, 31, 61, 89, 107, 127,
   521, 607, 1279, 2203, 2281, 3217, 4253, 4423, 9689, 9941, 11213,
   19937, 21701, 23209, 44497, 86243, 110503, 132049, 216091, 756839,
@@ -11,17 +17,104 @@ const MERSENNE_PRIMES: [u32; 43] = [2, 3, 5, 7, 13, 17, 19, 31, 61, 89, 107, 127
   20996011, 24036583, 25964951, 30402457];
 
 fn main() {
+    // --test=ll (the default) or --test=prp
+    let test = match Test::parse(&get_flag("test").unwrap_or_else(|| String::from("ll"))) {
+        Ok(test) => test,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
+
+    // Unfinished tests are saved every --checkpoint-interval seconds (0 turns this off)
+    // and picked up again on the next run
+    let checkpoints = Checkpoints::new(
+        PathBuf::from(get_flag("checkpoint-dir").unwrap_or_else(|| String::from("checkpoints"))),
+        Duration::from_secs(
+            get_flag("checkpoint-interval")
+                .and_then(|secs| secs.parse().ok())
+                .unwrap_or(600),
+        ),
+        test,
+    );
+
+    // Every result is appended to the ledger and compared with earlier runs
+    let ledger_path = get_flag("ledger").unwrap_or_else(|| String::from("results.jsonl"));
+    let ledger = Ledger::open(Path::new(&ledger_path)).expect("Could not open the ledger");
+    println!("Ledger {} holds {} earlier results", ledger_path, ledger.known_results());
+
+    // Trial factoring runs up to 2^depth, by default depending on p (0 turns it off)
+    let tf_depth: Option<u32> = get_flag("tf-depth").and_then(|depth| depth.parse().ok());
+
     // Generate the primes from the given span
-    println!("Verying Mersenne primes...");
+    println!("Verying Mersenne primes with {}...", test.name());
 
     for (i, &prime) in MERSENNE_PRIMES.iter().enumerate() {
         print!("Checking #{:2} = {:9}  ", i+1, prime);
         let now = Instant::now();
 
-        let is_prime = primes::is_mersenne_prime(prime);
+        // None of these has a factor, finding one (or knowing of one) means something is off
+        let checkpoint = checkpoints.load(prime);
+        let factor = match ledger.factor(prime) {
+            Some(q) => Some(q),
+            None if checkpoint.is_some() => None,
+            None => {
+                let depth = tf_depth.unwrap_or_else(|| factor::default_depth(prime));
+                if ledger.factored_to(prime, depth) {
+                    None
+                } else {
+                    let factor = match factor::trial_factor(prime, depth, &mut || true) {
+                        Some(Factoring::Factor(q)) => Some(q.to_string()),
+                        _ => None,
+                    };
+                    ledger.append(&Record {
+                        depth: Some(depth),
+                        ..Record::factor(prime, "TF", factor.clone(), now.elapsed())
+                    });
+                    factor
+                }
+            }
+        };
+        if let Some(q) = factor {
+            println!("NG [{}] (factor {})", time::format_time(now.elapsed().as_millis()), q);
+            continue;
+        }
+
+        let start = checkpoint.unwrap_or_else(|| test.start());
+        if start.iteration > 0 {
+            print!("(resumed at {}) ", start.iteration);
+        }
+        let mut saver = checkpoints.saver(prime);
+        let mut safe_point = |residue: &_, _| {
+            saver.tick(residue, false);
+            true
+        };
+        let mut squarer = Plain::new(prime, &|| 1);
+        let outcome = match test.run(prime, start, &mut squarer, &ErrorChecks::default(), &mut safe_point) {
+            Ok(outcome) => outcome,
+            // The Gerbicz check of PRP kept failing; the checkpoint keeps the last good state
+            Err(_) => {
+                println!("ERR [{}] (checks keep failing)", time::format_time(now.elapsed().as_millis()));
+                continue;
+            }
+        };
+        checkpoints.remove(prime);
+
+        // Neither test applies to p = 2, so its result is not worth keeping
+        let check = if prime == 2 {
+            Check::New
+        } else {
+            ledger.append(&Record::new(prime, outcome, test.name(), now.elapsed()))
+        };
 
-        let msg = if is_prime { "OK" } else { "NG" };
+        let msg = if outcome.is_prime { "OK" } else { "NG" };
         let elapsed = now.elapsed().as_millis();
-        println!("{} [{}]", msg, time::format_time(elapsed));
+        let verified = match check {
+            Check::Matches => " (matches ledger)",
+            // Always unshifted here, so a match with an earlier run of this checker proves little
+            Check::Repeated => " (matches ledger, same shift)",
+            _ => "",
+        };
+        println!("{} [{}]{}", msg, time::format_time(elapsed), verified);
     }
 }
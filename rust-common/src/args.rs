@@ -0,0 +1,99 @@
+use std::env;
+use std::fs;
+
+fn strip_characters(original: &str, to_strip: &str) -> String {
+    original
+        .chars()
+        .filter(|&c| !to_strip.contains(c))
+        .collect()
+}
+
+fn get_and_parse(arg: Option<&String>, default: u32) -> u32 {
+
+    match arg {
+        Some(s) => strip_characters(s, "_").parse().unwrap_or(default),
+        None => default
+    }
+}
+
+// Options are given as `--name=value` and may appear anywhere on the command line.
+// Everything else is a positional argument.
+
+fn is_flag(arg: &str) -> bool {
+    arg.starts_with("--")
+}
+
+pub fn positional_args() -> Vec<String> {
+    env::args().filter(|arg| !is_flag(arg)).collect()
+}
+
+pub fn get_flag(name: &str) -> Option<String> {
+    let prefix = format!("--{}=", name);
+    env::args()
+        .filter(|arg| is_flag(arg))
+        .find_map(|arg| arg.strip_prefix(&prefix).map(String::from))
+}
+
+pub fn get_flag_u32(name: &str, default: u32) -> u32 {
+    get_and_parse(get_flag(name).as_ref(), default)
+}
+
+// Settings may also come from the environment as MERSENNE_<NAME> (e.g. MERSENNE_THREADS),
+// or from a config file of `name = value` lines (--config, mersenne.conf by default).
+// The command line wins over the environment, which wins over the file.
+
+fn env_name(name: &str) -> String {
+    format!("MERSENNE_{}", name.to_uppercase().replace('-', "_"))
+}
+
+fn parse_config(text: &str, name: &str) -> Option<String> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter_map(|line| line.split_once('='))
+        .find(|(key, _)| key.trim() == name)
+        .map(|(_, value)| value.trim().to_owned())
+}
+
+fn config_value(name: &str) -> Option<String> {
+    let path = get_flag("config").unwrap_or_else(|| String::from("mersenne.conf"));
+    let text = fs::read_to_string(path).ok()?;
+    parse_config(&text, name)
+}
+
+pub fn get_setting(name: &str) -> Option<String> {
+    get_flag(name)
+        .or_else(|| env::var(env_name(name)).ok())
+        .or_else(|| config_value(name))
+}
+
+pub fn get_setting_u32(name: &str, default: u32) -> u32 {
+    get_and_parse(get_setting(name).as_ref(), default)
+}
+
+pub fn parse_cmd_args() -> (u32, u32) {
+    let args: Vec<String> = positional_args();
+
+    if args.len() > 2 {
+        let lower_bound = get_and_parse(args.get(1), 2);
+        let upper_bound = get_and_parse(args.get(2), 1000);
+        (lower_bound, upper_bound)
+    } else {
+        let lower_bound = 2;
+        let upper_bound = get_and_parse(args.get(1), 1000);
+        (lower_bound, upper_bound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_files() {
+        let text = "# worker settings\nthreads = 8\n\norder=interleaved # for the dashboard\n";
+        assert_eq!(parse_config(text, "threads"), Some(String::from("8")));
+        assert_eq!(parse_config(text, "order"), Some(String::from("interleaved")));
+        assert_eq!(parse_config(text, "ledger"), None);
+        assert_eq!(env_name("checkpoint-dir"), "MERSENNE_CHECKPOINT_DIR");
+    }
+}
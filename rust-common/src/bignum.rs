@@ -0,0 +1,346 @@
+use std::fmt::Debug;
+
+// The big integers under the LL and PRP tests, picked with cargo features: rug
+// (GMP, the default and the fastest) or num-bigint, which is pure Rust, so
+// rust-mersenne builds without GMP and the num_bigint rows of the README can be redone:
+//
+//   cargo build --release --no-default-features --features num-bigint
+//
+// server and rust-checker use rug beyond this trait, so they always need GMP.
+// With both on rug is used, and the tests also run the two against each other.
+
+#[cfg(not(any(feature = "rug", feature = "num-bigint")))]
+compile_error!("either the rug or the num-bigint feature is needed");
+
+#[cfg(feature = "rug")]
+pub type Integer = rug::Integer;
+
+#[cfg(not(feature = "rug"))]
+pub type Integer = num_bigint::BigUint;
+
+#[cfg(feature = "num-bigint")]
+pub use num_bigint::BigUint;
+
+// Everything the tests do with their numbers, which are never negative.
+// Arithmetic mod M_p avoids division: 2^p = 1 (mod M_p), so the bits of x from
+// p up can simply be added onto its low p bits, (x & M_p) + (x >> p).
+pub trait Bignum: Clone + Debug + PartialEq + Send + Sync + 'static {
+    fn from_u32(n: u32) -> Self;
+
+    // M_p = 2^p - 1
+    fn mersenne(prime: u32) -> Self;
+
+    fn square(&mut self);
+
+    fn mul(&mut self, other: &Self);
+
+    // Bits from..from + len of x, as a number
+    fn bit_slice(&self, from: u32, len: u32) -> Self;
+
+    // x + other * 2^shift; `scratch` as for reduce
+    fn add_shifted(&mut self, other: &Self, shift: u32, scratch: &mut Self);
+
+    // x - other, for other <= x
+    fn sub(&mut self, other: &Self);
+
+    // x mod M_p. A square of a reduced x takes one fold to get below 2^p, which
+    // leaves M_p itself as the only value still to be brought to 0. `scratch`
+    // holds the high bits; kept by the caller, it saves an allocation per fold.
+    fn reduce(&mut self, prime: u32, modulus: &Self, scratch: &mut Self);
+
+    // x - 2^k mod M_p, for reduced x and k < p; `scratch` as for reduce
+    fn sub_power_of_two(&mut self, k: u32, modulus: &Self, scratch: &mut Self);
+
+    // The Jacobi symbol (x | n), for odd n
+    fn jacobi(&self, n: &Self) -> i32;
+
+    fn is_zero(&self) -> bool;
+
+    // The low 64 bits, for the res64
+    fn low_u64(&self) -> u64;
+
+    fn significant_bits(&self) -> u32;
+
+    // 64-bit limbs, least significant first
+    fn to_limbs(&self) -> Vec<u64>;
+
+    fn from_limbs(limbs: &[u64]) -> Self;
+
+    // Lower case hex, as written to checkpoints
+    fn to_hex(&self) -> String;
+
+    fn from_hex(hex: &str) -> Option<Self>;
+}
+
+#[cfg(feature = "rug")]
+impl Bignum for rug::Integer {
+    fn from_u32(n: u32) -> Self {
+        rug::Integer::from(n)
+    }
+
+    fn mersenne(prime: u32) -> Self {
+        let mut m = rug::Integer::from(1) << prime;
+        m -= 1;
+        m
+    }
+
+    fn square(&mut self) {
+        self.square_mut();
+    }
+
+    fn mul(&mut self, other: &Self) {
+        *self *= other;
+    }
+
+    fn bit_slice(&self, from: u32, len: u32) -> Self {
+        use rug::Assign;
+        let mut slice = rug::Integer::new();
+        slice.assign(self >> from);
+        slice.keep_bits_mut(len);
+        slice
+    }
+
+    fn add_shifted(&mut self, other: &Self, shift: u32, scratch: &mut Self) {
+        use rug::Assign;
+        if shift == 0 {
+            *self += other;
+            return;
+        }
+        scratch.assign(other << shift);
+        *self += &*scratch;
+    }
+
+    fn sub(&mut self, other: &Self) {
+        *self -= other;
+    }
+
+    fn reduce(&mut self, prime: u32, modulus: &Self, scratch: &mut Self) {
+        use rug::Assign;
+        while rug::Integer::significant_bits(self) > prime {
+            scratch.assign(&*self >> prime);
+            self.keep_bits_mut(prime);
+            *self += &*scratch;
+        }
+        if *self == *modulus {
+            self.assign(0);
+        }
+    }
+
+    fn sub_power_of_two(&mut self, k: u32, modulus: &Self, scratch: &mut Self) {
+        use rug::Assign;
+        scratch.assign(1);
+        *scratch <<= k;
+        *self -= &*scratch;
+        if *self < 0 {
+            *self += modulus;
+        }
+    }
+
+    fn jacobi(&self, n: &Self) -> i32 {
+        rug::Integer::jacobi(self, n)
+    }
+
+    fn is_zero(&self) -> bool {
+        *self == 0
+    }
+
+    fn low_u64(&self) -> u64 {
+        self.to_u64_wrapping()
+    }
+
+    fn significant_bits(&self) -> u32 {
+        rug::Integer::significant_bits(self)
+    }
+
+    fn to_limbs(&self) -> Vec<u64> {
+        self.to_digits(rug::integer::Order::Lsf)
+    }
+
+    fn from_limbs(limbs: &[u64]) -> Self {
+        rug::Integer::from_digits(limbs, rug::integer::Order::Lsf)
+    }
+
+    fn to_hex(&self) -> String {
+        self.to_string_radix(16)
+    }
+
+    fn from_hex(hex: &str) -> Option<Self> {
+        rug::Integer::from_str_radix(hex, 16).ok().filter(|x| *x >= 0)
+    }
+}
+
+#[cfg(feature = "num-bigint")]
+impl Bignum for num_bigint::BigUint {
+    fn from_u32(n: u32) -> Self {
+        num_bigint::BigUint::from(n)
+    }
+
+    fn mersenne(prime: u32) -> Self {
+        (num_bigint::BigUint::from(1u32) << prime) - 1u32
+    }
+
+    fn square(&mut self) {
+        *self = &*self * &*self;
+    }
+
+    fn mul(&mut self, other: &Self) {
+        *self *= other;
+    }
+
+    fn bit_slice(&self, from: u32, len: u32) -> Self {
+        let mask = (num_bigint::BigUint::from(1u32) << len) - 1u32;
+        (self >> from) & mask
+    }
+
+    fn add_shifted(&mut self, other: &Self, shift: u32, _scratch: &mut Self) {
+        *self += other << shift;
+    }
+
+    fn sub(&mut self, other: &Self) {
+        *self -= other;
+    }
+
+    fn reduce(&mut self, prime: u32, modulus: &Self, scratch: &mut Self) {
+        while self.bits() > prime as u64 {
+            // Low bits into `scratch`, high ones shifted down in place, where
+            // `&*self >> prime` would allocate. num-bigint still reallocates when
+            // a shift leaves its buffer less than half full, and allocates every
+            // product, so only `scratch` is certain to be reused.
+            scratch.clone_from(modulus);
+            *scratch &= &*self;
+            *self >>= prime;
+            *self += &*scratch;
+        }
+        if *self == *modulus {
+            *self = num_bigint::BigUint::default();
+        }
+    }
+
+    // No negative numbers here, so M_p goes on first
+    fn sub_power_of_two(&mut self, k: u32, modulus: &Self, scratch: &mut Self) {
+        *scratch = num_bigint::BigUint::from(1u32) << k;
+        if *self < *scratch {
+            *self += modulus;
+        }
+        *self -= &*scratch;
+    }
+
+    // The binary algorithm: (2 | n) = -1 for n = 3, 5 mod 8, and swapping x
+    // and n flips the sign when both are 3 mod 4
+    fn jacobi(&self, n: &Self) -> i32 {
+        let (mut x, mut n) = (self % n, n.clone());
+        let mut symbol = 1;
+        while x.bits() > 0 {
+            let twos = x.trailing_zeros().unwrap_or(0);
+            x >>= twos;
+            if twos % 2 == 1 && matches!(n.low_u64() % 8, 3 | 5) {
+                symbol = -symbol;
+            }
+            std::mem::swap(&mut x, &mut n);
+            if x.low_u64() % 4 == 3 && n.low_u64() % 4 == 3 {
+                symbol = -symbol;
+            }
+            x %= &n;
+        }
+        match n == num_bigint::BigUint::from(1u32) {
+            true => symbol,
+            false => 0,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.bits() == 0
+    }
+
+    fn low_u64(&self) -> u64 {
+        self.iter_u64_digits().next().unwrap_or(0)
+    }
+
+    fn significant_bits(&self) -> u32 {
+        self.bits() as u32
+    }
+
+    fn to_limbs(&self) -> Vec<u64> {
+        self.to_u64_digits()
+    }
+
+    fn from_limbs(limbs: &[u64]) -> Self {
+        let digits = limbs.iter().flat_map(|&limb| [limb as u32, (limb >> 32) as u32]);
+        num_bigint::BigUint::new(digits.collect())
+    }
+
+    fn to_hex(&self) -> String {
+        self.to_str_radix(16)
+    }
+
+    fn from_hex(hex: &str) -> Option<Self> {
+        num_bigint::BigUint::parse_bytes(hex.as_bytes(), 16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // p = 61 keeps everything within a u128 to check against
+    fn arithmetic<N: Bignum>() {
+        let p = 61;
+        let m = (1u128 << p) - 1;
+        let (modulus, mut scratch) = (N::mersenne(p), N::from_u32(0));
+        let (mut x, mut expected) = (N::from_u32(3), 3u128);
+        for k in 0..300 {
+            x.square();
+            x.reduce(p, &modulus, &mut scratch);
+            x.sub_power_of_two(k % p, &modulus, &mut scratch);
+            expected = (expected * expected % m + m - (1 << (k % p))) % m;
+            assert_eq!(x.to_hex(), format!("{:x}", expected), "after {} steps", k);
+        }
+        x.mul(&modulus);
+        x.reduce(p, &modulus, &mut scratch);
+        assert!(x.is_zero());
+        x.sub_power_of_two(0, &modulus, &mut scratch);
+        assert_eq!((x.significant_bits(), x.low_u64()), (p, m as u64 - 1));
+        assert_eq!(x.bit_slice(0, 8).low_u64(), 0xfe);
+        x.add_shifted(&N::from_u32(1), 100, &mut scratch);
+        assert_eq!(x.to_hex(), format!("1{:025x}", m - 1));
+        assert_eq!(x.to_limbs(), [m as u64 - 1, 1 << 36]);
+        assert_eq!(N::from_limbs(&x.to_limbs()), x);
+        assert_eq!(N::from_hex("xyz"), None);
+        // 2 is a square mod M_p, 3 is not, and 2^61 - 1 is prime
+        let (two, three) = (N::from_u32(2), N::from_u32(3));
+        assert_eq!((two.jacobi(&modulus), three.jacobi(&modulus)), (1, -1));
+        assert_eq!((modulus.jacobi(&modulus), three.jacobi(&N::from_u32(15))), (0, 0));
+        assert_eq!(N::from_u32(2).jacobi(&N::from_u32(15)), 1);
+    }
+
+    #[cfg(feature = "rug")]
+    #[test]
+    fn rug_backend() {
+        arithmetic::<rug::Integer>();
+    }
+
+    #[cfg(feature = "num-bigint")]
+    #[test]
+    fn num_bigint_backend() {
+        arithmetic::<BigUint>();
+    }
+
+    #[cfg(all(feature = "rug", feature = "num-bigint"))]
+    #[test]
+    fn backends_agree() {
+        let p = 521;
+        let (ma, mb) = (rug::Integer::mersenne(p), BigUint::mersenne(p));
+        let (mut sa, mut sb) = (rug::Integer::from_u32(0), BigUint::from_u32(0));
+        let (mut a, mut b) = (rug::Integer::from_u32(3), BigUint::from_u32(3));
+        for k in 0..300 {
+            Bignum::square(&mut a);
+            Bignum::square(&mut b);
+            a.reduce(p, &ma, &mut sa);
+            b.reduce(p, &mb, &mut sb);
+            a.sub_power_of_two(k, &ma, &mut sa);
+            b.sub_power_of_two(k, &mb, &mut sb);
+            assert_eq!(a.to_hex(), b.to_hex(), "after {} steps", k);
+        }
+        assert_eq!(BigUint::from_hex(&a.to_hex()), Some(b));
+    }
+}
@@ -0,0 +1,244 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::bignum::{Bignum, Integer};
+use crate::primes::{Residue, Test};
+
+// The state of an unfinished test, one file per exponent and kind of test:
+//
+//   mersenne-checkpoint 1
+//   exponent 86243
+//   iteration 4096
+//   residue 1f3a...      (hex)
+//   shift 40123          (shifted double-checks only)
+//   checksum 8c2d...     (FNV-1a of everything above)
+//
+// A file is replaced by writing a temporary one, syncing it and renaming it over
+// the old one, so a crash leaves either the previous checkpoint or the new one.
+
+const MAGIC: &str = "mersenne-checkpoint 1";
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn encode(prime: u32, residue: &Residue) -> String {
+    let mut body = format!(
+        "{}\nexponent {}\niteration {}\nresidue {}\n",
+        MAGIC,
+        prime,
+        residue.iteration,
+        residue.value.to_hex()
+    );
+    if residue.shift > 0 {
+        body.push_str(&format!("shift {}\n", residue.shift));
+    }
+    let checksum = fnv1a(body.as_bytes());
+    format!("{}checksum {:016x}\n", body, checksum)
+}
+
+fn decode(text: &str, prime: u32, test: Test) -> Result<Residue, String> {
+    let body_end = text.rfind("checksum ").ok_or("no checksum")?;
+    let (body, checksum) = text.split_at(body_end);
+    let checksum = u64::from_str_radix(checksum["checksum ".len()..].trim(), 16)
+        .map_err(|_| "unreadable checksum")?;
+    if fnv1a(body.as_bytes()) != checksum {
+        return Err(String::from("checksum mismatch"));
+    }
+
+    let mut lines = body.lines();
+    if lines.next() != Some(MAGIC) {
+        return Err(String::from("not a checkpoint file"));
+    }
+    let mut field = |name: &str| {
+        lines
+            .next()
+            .and_then(|line| line.strip_prefix(name))
+            .and_then(|value| value.strip_prefix(' '))
+            .ok_or(format!("missing \"{}\"", name))
+    };
+    let exponent: u32 = field("exponent")?.parse().map_err(|_| "bad exponent")?;
+    let iteration: u32 = field("iteration")?.parse().map_err(|_| "bad iteration")?;
+    let value = Integer::from_hex(field("residue")?).ok_or("bad residue")?;
+    let shift: u32 = match lines.next().and_then(|line| line.strip_prefix("shift ")) {
+        Some(shift) => shift.parse().map_err(|_| "bad shift")?,
+        None => 0,
+    };
+
+    if exponent != prime {
+        return Err(format!("written for exponent {}", exponent));
+    }
+    if iteration > test.iterations(prime) || value.significant_bits() > prime || shift >= prime {
+        return Err(String::from("residue out of range"));
+    }
+    Ok(Residue {
+        iteration,
+        value,
+        shift,
+    })
+}
+
+// Where checkpoints live and how often they are written.
+// A zero interval turns checkpointing off: nothing is loaded or saved.
+
+pub struct Checkpoints {
+    dir: Option<PathBuf>,
+    interval: Duration,
+    test: Test,
+    double_check: bool,
+}
+
+impl Checkpoints {
+    pub fn new(dir: PathBuf, interval: Duration, test: Test) -> Checkpoints {
+        let dir = if interval > Duration::from_secs(0) { Some(dir) } else { None };
+        Checkpoints {
+            dir,
+            interval,
+            test,
+            double_check: false,
+        }
+    }
+
+    // Double-checks keep their own files, they must not pick up where the first test stopped
+    pub fn for_double_checks(self) -> Checkpoints {
+        Checkpoints {
+            double_check: true,
+            ..self
+        }
+    }
+
+    // LL checkpoints came first and kept the plain name, e.g. M86243.ckpt, M86243.prp.ckpt,
+    // and for double-checks M86243.dc.ckpt and M86243.prp.dc.ckpt
+    fn path(&self, prime: u32) -> Option<PathBuf> {
+        let test = match self.test {
+            Test::LucasLehmer => String::new(),
+            test => format!(".{}", test.name().to_lowercase()),
+        };
+        let double_check = if self.double_check { ".dc" } else { "" };
+        let name = format!("M{}{}{}.ckpt", prime, test, double_check);
+        self.dir.as_ref().map(|dir| dir.join(name))
+    }
+
+    // A damaged or mismatched file is reported and ignored, the test then starts over
+    pub fn load(&self, prime: u32) -> Option<Residue> {
+        let path = self.path(prime)?;
+        let text = fs::read_to_string(&path).ok()?;
+        match decode(&text, prime, self.test) {
+            Ok(residue) => Some(residue),
+            Err(message) => {
+                eprintln!("Ignoring checkpoint {}: {}", path.display(), message);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, prime: u32, residue: &Residue) -> io::Result<()> {
+        let (dir, path) = match (&self.dir, self.path(prime)) {
+            (Some(dir), Some(path)) => (dir, path),
+            _ => return Ok(()),
+        };
+        fs::create_dir_all(dir)?;
+        let temp = path.with_extension("ckpt.tmp");
+        let mut file = File::create(&temp)?;
+        file.write_all(encode(prime, residue).as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, &path)
+    }
+
+    // Called once the test has a result
+    pub fn remove(&self, prime: u32) {
+        if let Some(path) = self.path(prime) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    pub fn saver(&self, prime: u32) -> Saver<'_> {
+        Saver {
+            checkpoints: self,
+            prime,
+            last_save: Instant::now(),
+        }
+    }
+}
+
+// Saves one exponent's residue every `interval` while its test runs
+
+pub struct Saver<'a> {
+    checkpoints: &'a Checkpoints,
+    prime: u32,
+    last_save: Instant,
+}
+
+impl<'a> Saver<'a> {
+    pub fn tick(&mut self, residue: &Residue, force: bool) {
+        let due = force || self.last_save.elapsed() >= self.checkpoints.interval;
+        if !due || self.checkpoints.dir.is_none() {
+            return;
+        }
+        if let Err(error) = self.checkpoints.save(self.prime, residue) {
+            eprintln!("Could not checkpoint M{}: {}", self.prime, error);
+        }
+        self.last_save = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_and_corruption() {
+        let residue = Residue {
+            iteration: 7,
+            value: Integer::from_u32(0x1234_5678),
+            shift: 0,
+        };
+        let text = encode(31, &residue);
+        assert_eq!(decode(&text, 31, Test::LucasLehmer), Ok(residue));
+        let shifted = Residue::shifted_start(31, 17);
+        assert_eq!(decode(&encode(31, &shifted), 31, Test::LucasLehmer), Ok(shifted));
+        assert!(decode(&text, 61, Test::LucasLehmer).is_err());
+        assert!(decode(&text.replace("iteration 7", "iteration 8"), 31, Test::Prp).is_err());
+        assert!(decode(&text[..text.len() - 4], 31, Test::Prp).is_err());
+    }
+
+    #[test]
+    fn save_load_remove() {
+        let dir = std::env::temp_dir().join(format!("checkpoints-{}", std::process::id()));
+        let checkpoints = Checkpoints::new(dir.clone(), Duration::from_secs(60), Test::LucasLehmer);
+        assert_eq!(checkpoints.load(127), None);
+
+        let residue = Residue {
+            iteration: 100,
+            value: Integer::from_u32(12345),
+            shift: 0,
+        };
+        checkpoints.save(127, &residue).unwrap();
+        assert_eq!(checkpoints.load(127), Some(residue));
+        let prp = Checkpoints::new(dir.clone(), Duration::from_secs(60), Test::Prp);
+        assert_eq!(prp.load(127), None);
+        let double_checks = Checkpoints::new(dir.clone(), Duration::from_secs(60), Test::LucasLehmer);
+        let double_checks = double_checks.for_double_checks();
+        assert_eq!(double_checks.load(127), None);
+        // Nor does an LL double-check pick up a PRP one
+        let prp_double_checks = prp.for_double_checks();
+        let prp_residue = Residue {
+            iteration: 120,
+            ..Residue::start()
+        };
+        prp_double_checks.save(127, &prp_residue).unwrap();
+        assert_eq!(double_checks.load(127), None);
+        assert_eq!(prp_double_checks.load(127), Some(prp_residue));
+
+        checkpoints.remove(127);
+        assert_eq!(checkpoints.load(127), None);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
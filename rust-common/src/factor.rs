@@ -0,0 +1,143 @@
+// Trial factoring. Any factor q of M_p (p an odd prime) has the form q = 2kp + 1
+// with q = ±1 (mod 8), so only those q are tried: the k are sieved by small primes
+// and each survivor is checked with 2^p mod q == 1. Most composite M_p have a
+// small factor, and finding it is far cheaper than a Lucas-Lehmer test.
+
+// Odd primes up to this bound strike out the k whose q they divide
+const SIEVE_LIMIT: u32 = 1000;
+// k values sieved at a time
+const BLOCK: usize = 1 << 15;
+
+// How far up to look when no depth is configured: q < 2^depth.
+// It grows with p because the LL test that a factor saves grows much faster.
+pub fn default_depth(prime: u32) -> u32 {
+    let bits = 32 - prime.leading_zeros();
+    (2 * bits + 4).min(64)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Factoring {
+    Factor(u64),
+    NoFactor,
+}
+
+fn small_primes() -> Vec<u32> {
+    let mut primes: Vec<u32> = vec![];
+    for n in (3..SIEVE_LIMIT).step_by(2) {
+        if primes.iter().take_while(|&&r| r * r <= n).all(|&r| n % r != 0) {
+            primes.push(n);
+        }
+    }
+    primes
+}
+
+// 2^p mod q by square and multiply
+fn pow2_mod(prime: u32, q: u64) -> u64 {
+    let q = q as u128;
+    let mut x: u128 = 1;
+    for bit in (0..32 - prime.leading_zeros()).rev() {
+        x = x * x % q;
+        if prime >> bit & 1 == 1 {
+            x = x * 2 % q;
+        }
+    }
+    x as u64
+}
+
+// The inverse of a mod r, r prime and a not a multiple of r
+fn inverse_mod(a: u64, r: u64) -> u64 {
+    let mut x = 1;
+    let mut base = a % r;
+    let mut e = r - 2;
+    while e > 0 {
+        if e & 1 == 1 {
+            x = x * base % r;
+        }
+        base = base * base % r;
+        e >>= 1;
+    }
+    x
+}
+
+// Looks for the smallest factor of M_p below 2^depth (at most 2^64, and below
+// 2^(p-1) so M_p itself never counts). `keep_going` is asked between blocks of
+// candidates; once it says no the search is abandoned and the result is None.
+pub fn trial_factor(prime: u32, depth: u32, keep_going: &mut dyn FnMut() -> bool) -> Option<Factoring> {
+    let depth = depth.min(64).min(prime.saturating_sub(1));
+    if prime < 3 || depth < 2 {
+        return Some(Factoring::NoFactor);
+    }
+    let p = prime as u128;
+    let max_k = ((1u128 << depth) - 2) / (2 * p);
+
+    // For each sieving prime r, q is a multiple of r exactly when k = first (mod r)
+    let sieve: Vec<(u64, u64)> = small_primes()
+        .into_iter()
+        .filter(|&r| r != prime)
+        .map(|r| {
+            let r = r as u64;
+            (r, r - inverse_mod(2 * prime as u64 % r, r))
+        })
+        .collect();
+
+    let mut composite = vec![false; BLOCK];
+    let mut k0: u128 = 1;
+    while k0 <= max_k {
+        if !keep_going() {
+            return None;
+        }
+        let len = (max_k - k0 + 1).min(BLOCK as u128) as usize;
+        composite[..len].iter_mut().for_each(|c| *c = false);
+        for &(r, first) in &sieve {
+            let mut i = ((first as u128 + r as u128 - k0 % r as u128) % r as u128) as usize;
+            // r itself may be a candidate, it must not strike itself out
+            if 2 * (k0 + i as u128) * p + 1 == r as u128 {
+                i += r as usize;
+            }
+            while i < len {
+                composite[i] = true;
+                i += r as usize;
+            }
+        }
+        for (i, _) in composite[..len].iter().enumerate().filter(|(_, &c)| !c) {
+            let q = (2 * (k0 + i as u128) * p + 1) as u64;
+            if matches!(q % 8, 1 | 7) && pow2_mod(prime, q) == 1 {
+                return Some(Factoring::Factor(q));
+            }
+        }
+        k0 += len as u128;
+    }
+    Some(Factoring::NoFactor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn factor(prime: u32, depth: u32) -> Factoring {
+        trial_factor(prime, depth, &mut || true).unwrap()
+    }
+
+    #[test]
+    fn known_factors() {
+        assert_eq!(factor(11, 20), Factoring::Factor(23));
+        assert_eq!(factor(23, 20), Factoring::Factor(47));
+        assert_eq!(factor(37, 20), Factoring::Factor(223));
+        assert_eq!(factor(67, 30), Factoring::Factor(193707721));
+        assert_eq!(factor(67, 27), Factoring::NoFactor);
+        assert_eq!(factor(3, 64), Factoring::NoFactor);
+        assert_eq!(factor(127, 28), Factoring::NoFactor);
+        assert_eq!(trial_factor(67, 30, &mut || false), None);
+    }
+
+    #[test]
+    fn factors_divide_the_mersenne_number() {
+        for prime in (3..128).filter(|&p| (2..p).all(|d| p % d != 0)) {
+            if let Factoring::Factor(q) = factor(prime, default_depth(prime)) {
+                let mersenne = (1u128 << prime) - 1;
+                assert_eq!(mersenne % q as u128, 0, "M{} by {}", prime, q);
+                assert!((q as u128) < mersenne);
+            }
+        }
+    }
+}
@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::json::{self, Value};
+use crate::primes::Outcome;
+
+// An append-only JSON Lines file with one record per tested exponent, e.g.
+//
+//   {"exponent": 11, "prime": false, "res64": "00000000000006c8", "test": "LL",
+//    "secs": 0.001, "host": "box", "finished": 1700000000}
+//
+// Factoring records ("TF" for trial factoring, "PM1" for P-1) carry the factor
+// found, or null, instead of a res64; P-1 records also give their bounds B1 and B2,
+// trial factoring records the depth d of a search below 2^d.
+// Shifted LL double-checks give the shift they started with.
+// Records from earlier runs are read back at startup, so when an exponent is
+// tested again its result is compared with what the ledger already holds.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub exponent: u32,
+    pub is_prime: bool,
+    pub res64: Option<u64>,
+    // In decimal, P-1 finds factors of any size
+    pub factor: Option<String>,
+    pub bounds: Option<(u64, u64)>,
+    pub depth: Option<u32>,
+    pub shift: Option<u32>,
+    pub test: String,
+    pub secs: f64,
+    pub host: String,
+    pub finished: u64,
+}
+
+impl Record {
+    pub fn new(exponent: u32, outcome: Outcome, test: &str, took: Duration) -> Record {
+        Record {
+            exponent,
+            is_prime: outcome.is_prime,
+            res64: Some(outcome.res64),
+            factor: None,
+            bounds: None,
+            depth: None,
+            shift: None,
+            test: String::from(test),
+            secs: took.as_secs_f64(),
+            host: hostname(),
+            finished: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+        }
+    }
+
+    // The result of a factoring attempt, `factor` being None when it found nothing
+    pub fn factor(exponent: u32, test: &str, factor: Option<String>, took: Duration) -> Record {
+        Record {
+            res64: None,
+            factor,
+            ..Record::new(exponent, Outcome { is_prime: false, res64: 0 }, test, took)
+        }
+    }
+
+    // What a rerun of the same test has to reproduce
+    fn result(&self) -> String {
+        match (self.res64, &self.factor) {
+            (Some(res64), _) => format!("{:016x}", res64),
+            (None, Some(factor)) => factor.clone(),
+            (None, None) => String::from("no factor"),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        // Factors are strings too: JSON numbers lose precision above 2^53
+        let mut result = match (self.res64, &self.factor) {
+            (Some(res64), _) => format!("\"res64\": \"{:016x}\"", res64),
+            (None, Some(factor)) => format!("\"factor\": {}", json::string(factor)),
+            (None, None) => String::from("\"factor\": null"),
+        };
+        if let Some((b1, b2)) = self.bounds {
+            result.push_str(&format!(", \"b1\": {}, \"b2\": {}", b1, b2));
+        }
+        if let Some(depth) = self.depth {
+            result.push_str(&format!(", \"depth\": {}", depth));
+        }
+        if let Some(shift) = self.shift {
+            result.push_str(&format!(", \"shift\": {}", shift));
+        }
+        format!(
+            "{{\"exponent\": {}, \"prime\": {}, {}, \"test\": {}, \
+             \"secs\": {}, \"host\": {}, \"finished\": {}}}",
+            self.exponent,
+            self.is_prime,
+            result,
+            json::string(&self.test),
+            json::f64(self.secs),
+            json::string(&self.host),
+            self.finished
+        )
+    }
+
+    fn parse(line: &str) -> Option<Record> {
+        let value = json::parse(line).ok()?;
+        let string = |key: &str| match value.get(key) {
+            Some(Value::String(text)) => Some(text.clone()),
+            _ => None,
+        };
+        let number = |key: &str| match value.get(key) {
+            Some(Value::Number(n)) => Some(*n),
+            _ => None,
+        };
+        Some(Record {
+            exponent: value.get("exponent")?.as_u32()?,
+            is_prime: value.get("prime")? == &Value::Bool(true),
+            res64: string("res64").and_then(|res64| u64::from_str_radix(&res64, 16).ok()),
+            factor: string("factor"),
+            bounds: number("b1").zip(number("b2")).map(|(b1, b2)| (b1 as u64, b2 as u64)),
+            depth: number("depth").map(|depth| depth as u32),
+            shift: number("shift").map(|shift| shift as u32),
+            test: string("test")?,
+            secs: number("secs").unwrap_or(0.0),
+            host: string("host").unwrap_or_default(),
+            finished: number("finished").unwrap_or(0.0) as u64,
+        })
+    }
+}
+
+fn hostname() -> String {
+    let from_file = fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .ok();
+    from_file
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+// A result and the shift of the run that got it
+type Run = (String, u32);
+
+// Whether two runs agree with different shifts
+fn verifies((result, shift): &Run, (other, other_shift): &Run) -> bool {
+    result == other && shift != other_shift
+}
+
+// How a new result compares with the ones already in the ledger. Only a run
+// with another shift checks a result: one with the same shift squares the same
+// numbers, so it repeats any error the first run made.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Check {
+    New,
+    Matches,
+    // Agrees, but only with runs of the same shift
+    Repeated,
+    Mismatch(String),
+}
+
+pub struct Ledger {
+    file: Mutex<File>,
+    // Every result and its shift per exponent and test type, oldest first
+    known: Mutex<HashMap<(u32, String), Vec<Run>>>,
+    factors: Mutex<HashMap<u32, String>>,
+    // The bounds of every P-1 run per exponent, oldest first
+    pm1_bounds: Mutex<HashMap<u32, Vec<(u64, u64)>>>,
+    // The deepest trial factoring per exponent
+    tf_depths: Mutex<HashMap<u32, u32>>,
+}
+
+impl Ledger {
+    // Lines that cannot be parsed (say, cut short by a crash) are skipped
+    pub fn open(path: &Path) -> io::Result<Ledger> {
+        let ledger = Ledger {
+            file: Mutex::new(OpenOptions::new().create(true).append(true).open(path)?),
+            known: Mutex::new(HashMap::new()),
+            factors: Mutex::new(HashMap::new()),
+            pm1_bounds: Mutex::new(HashMap::new()),
+            tf_depths: Mutex::new(HashMap::new()),
+        };
+        if let Ok(text) = fs::read_to_string(path) {
+            for record in text.lines().filter_map(Record::parse) {
+                ledger.remember(&record);
+            }
+        }
+        Ok(ledger)
+    }
+
+    // Says how the record compares with earlier results of the same test
+    fn remember(&self, record: &Record) -> Check {
+        if let Some(factor) = &record.factor {
+            self.factors.lock().unwrap().insert(record.exponent, factor.clone());
+        }
+        if let Some(bounds) = record.bounds {
+            self.pm1_bounds.lock().unwrap().entry(record.exponent).or_default().push(bounds);
+        }
+        if let Some(depth) = record.depth {
+            let mut tf_depths = self.tf_depths.lock().unwrap();
+            let tried = tf_depths.entry(record.exponent).or_insert(depth);
+            *tried = (*tried).max(depth);
+        }
+        // Factoring only has to agree with searches just as deep
+        let test = match (record.depth, record.bounds) {
+            (Some(depth), _) => format!("{} to 2^{}", record.test, depth),
+            (None, Some((b1, b2))) => format!("{} to {}, {}", record.test, b1, b2),
+            (None, None) => record.test.clone(),
+        };
+        let key = (record.exponent, test);
+        let result = (record.result(), record.shift.unwrap_or(0));
+        let mut known = self.known.lock().unwrap();
+        let earlier = known.entry(key).or_default();
+        let check = match earlier.last() {
+            None => Check::New,
+            // A third run settles a mismatch by agreeing with either side
+            Some(_) if earlier.iter().any(|other| verifies(&result, other)) => Check::Matches,
+            Some(_) if earlier.iter().any(|(other, _)| *other == result.0) => Check::Repeated,
+            Some((previous, _)) => Check::Mismatch(previous.clone()),
+        };
+        earlier.push(result);
+        check
+    }
+
+    pub fn known_results(&self) -> usize {
+        self.known.lock().unwrap().len()
+    }
+
+    // Exponents with results of `test` that no run with another shift has
+    // reproduced yet, in increasing order: those never double-checked, those
+    // only rerun with the same shift and those whose checks disagree
+    pub fn unverified(&self, test: &str) -> Vec<u32> {
+        let known = self.known.lock().unwrap();
+        let mut exponents: Vec<u32> = known
+            .iter()
+            .filter(|((_, name), _)| name == test)
+            .filter(|(_, results)| {
+                !results.iter().enumerate().any(|(i, result)| {
+                    results[..i].iter().any(|other| verifies(result, other))
+                })
+            })
+            .map(|((exponent, _), _)| *exponent)
+            .collect();
+        exponents.sort();
+        exponents
+    }
+
+    // A factor of M_p found by this or an earlier run
+    pub fn factor(&self, exponent: u32) -> Option<String> {
+        self.factors.lock().unwrap().get(&exponent).cloned()
+    }
+
+    // The bounds (B1, B2) of each earlier P-1 run on M_p
+    pub fn pm1_bounds(&self, exponent: u32) -> Vec<(u64, u64)> {
+        self.pm1_bounds.lock().unwrap().get(&exponent).cloned().unwrap_or_default()
+    }
+
+    // Whether trial factoring of M_p already went as deep as 2^depth
+    pub fn factored_to(&self, exponent: u32, depth: u32) -> bool {
+        self.tf_depths.lock().unwrap().get(&exponent).is_some_and(|&tried| tried >= depth)
+    }
+
+    // Appends the record and says whether it agrees with earlier runs.
+    // A mismatch means one of the runs went wrong, so it is reported right away.
+    pub fn append(&self, record: &Record) -> Check {
+        let check = self.remember(record);
+        if let Check::Mismatch(previous) = &check {
+            eprintln!(
+                "Ledger mismatch for M{} ({}): {}, earlier runs had {}",
+                record.exponent,
+                record.test,
+                record.result(),
+                previous
+            );
+        }
+
+        let line = format!("{}\n", record.to_json());
+        let mut file = self.file.lock().unwrap();
+        if let Err(error) = file.write_all(line.as_bytes()).and_then(|_| file.flush()) {
+            eprintln!("Could not write to the ledger: {}", error);
+        }
+        check
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_round_trip() {
+        let outcome = Outcome {
+            is_prime: false,
+            res64: 1736,
+        };
+        let record = Record::new(11, outcome, "LL", Duration::from_millis(1500));
+        let line = record.to_json();
+        assert!(line.contains("\"res64\": \"00000000000006c8\""));
+        assert_eq!(Record::parse(&line), Some(record));
+        assert_eq!(Record::parse("{\"exponent\": 11, \"pri"), None);
+
+        let record = Record::factor(11, "TF", Some(String::from("23")), Duration::from_millis(1));
+        let line = record.to_json();
+        assert!(line.contains("\"factor\": \"23\"") && !line.contains("res64"));
+        assert_eq!(Record::parse(&line), Some(record));
+
+        let record = Record {
+            bounds: Some((1000, 20000)),
+            ..Record::factor(61, "PM1", None, Duration::from_millis(1))
+        };
+        let line = record.to_json();
+        assert!(line.contains("\"factor\": null, \"b1\": 1000, \"b2\": 20000"));
+        assert_eq!(Record::parse(&line), Some(record));
+
+        let record = Record {
+            depth: Some(30),
+            ..Record::factor(67, "TF", None, Duration::from_millis(1))
+        };
+        let line = record.to_json();
+        assert!(line.contains("\"factor\": null, \"depth\": 30"));
+        assert_eq!(Record::parse(&line), Some(record));
+
+        let record = Record {
+            shift: Some(5),
+            ..Record::new(11, outcome, "LL", Duration::from_millis(1))
+        };
+        let line = record.to_json();
+        assert!(line.contains("\"res64\": \"00000000000006c8\", \"shift\": 5"));
+        assert_eq!(Record::parse(&line), Some(record));
+    }
+
+    #[test]
+    fn later_runs_are_cross_checked() {
+        let path = std::env::temp_dir().join(format!("ledger-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let record = |res64, shift| {
+            let outcome = Outcome {
+                is_prime: false,
+                res64,
+            };
+            Record {
+                shift: Some(shift).filter(|&shift| shift > 0),
+                ..Record::new(11, outcome, "LL", Duration::from_secs(1))
+            }
+        };
+
+        let ledger = Ledger::open(&path).unwrap();
+        assert_eq!(ledger.append(&record(1736, 0)), Check::New);
+        drop(ledger);
+
+        let ledger = Ledger::open(&path).unwrap();
+        assert_eq!(ledger.known_results(), 1);
+        assert_eq!(ledger.unverified("LL"), vec![11]);
+        // The same numbers squared again prove nothing
+        assert_eq!(ledger.append(&record(1736, 0)), Check::Repeated);
+        assert_eq!(ledger.unverified("LL"), vec![11]);
+        assert_eq!(ledger.append(&record(1736, 3)), Check::Matches);
+        assert_eq!(ledger.unverified("LL"), vec![]);
+        let mismatch = Check::Mismatch(String::from("00000000000006c8"));
+        assert_eq!(ledger.append(&record(1737, 5)), mismatch);
+        assert_eq!(ledger.append(&record(1737, 5)), Check::Repeated);
+        assert_eq!(ledger.append(&record(1737, 7)), Check::Matches);
+        assert_eq!(ledger.factor(11), None);
+        ledger.append(&Record::factor(11, "TF", Some(String::from("23")), Duration::from_secs(1)));
+        let pm1 = |b1, b2| Record {
+            bounds: Some((b1, b2)),
+            ..Record::factor(61, "PM1", None, Duration::from_secs(1))
+        };
+        assert_eq!(ledger.append(&pm1(1000, 50000)), Check::New);
+        assert_eq!(ledger.append(&pm1(2000, 40000)), Check::New);
+        let tf = |depth, factor: Option<&str>| Record {
+            depth: Some(depth),
+            ..Record::factor(67, "TF", factor.map(String::from), Duration::from_secs(1))
+        };
+        assert_eq!(ledger.append(&tf(30, None)), Check::New);
+        assert_eq!(ledger.append(&tf(40, Some("193707721"))), Check::New);
+        let ledger = Ledger::open(&path).unwrap();
+        assert_eq!(ledger.factor(11), Some(String::from("23")));
+        // Each run as it was, neither went as far as (2000, 50000)
+        assert_eq!(ledger.pm1_bounds(61), vec![(1000, 50000), (2000, 40000)]);
+        assert_eq!(ledger.pm1_bounds(67), vec![]);
+        assert!(ledger.factored_to(67, 40) && !ledger.factored_to(67, 41));
+        assert!(!ledger.factored_to(61, 1));
+        assert_eq!(ledger.factor(67), Some(String::from("193707721")));
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 11);
+        let _ = fs::remove_file(path);
+    }
+}
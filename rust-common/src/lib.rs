@@ -0,0 +1,11 @@
+// Code shared by server, rust-mersenne and rust-checker
+
+pub mod args;
+pub mod bignum;
+pub mod checkpoint;
+pub mod factor;
+pub mod json;
+pub mod ledger;
+pub mod mersenne;
+pub mod primes;
+pub mod split;
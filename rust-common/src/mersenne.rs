@@ -0,0 +1,96 @@
+use crate::bignum::Bignum;
+use crate::split;
+
+// Arithmetic mod M_p without division (see Bignum::reduce). `scratch` holds the
+// high bits of a fold and is kept between iterations, so once it has grown to
+// size a reduction allocates nothing.
+pub struct Mersenne<N> {
+    pub prime: u32,
+    pub modulus: N,
+    scratch: N,
+}
+
+impl<N: Bignum> Mersenne<N> {
+    pub fn new(prime: u32) -> Mersenne<N> {
+        Mersenne {
+            prime,
+            modulus: N::mersenne(prime),
+            scratch: N::from_u32(0),
+        }
+    }
+
+    pub fn reduce(&mut self, x: &mut N) {
+        x.reduce(self.prime, &self.modulus, &mut self.scratch);
+    }
+
+    pub fn square(&mut self, x: &mut N) {
+        x.square();
+        self.reduce(x);
+    }
+
+    // The same with the squaring spread over up to `threads` threads
+    pub fn square_split(&mut self, x: &mut N, threads: usize) {
+        split::split_square(x, threads);
+        self.reduce(x);
+    }
+
+    // x - 2^k mod M_p, for reduced x and k <= p; 2^p is 1
+    pub fn sub_power_of_two(&mut self, x: &mut N, k: u32) {
+        let k = if k == self.prime { 0 } else { k };
+        x.sub_power_of_two(k, &self.modulus, &mut self.scratch);
+    }
+
+    // x * 2^k mod M_p, for reduced x and k < p: a rotation of the p bits of x
+    pub fn mul_power_of_two(&mut self, x: &mut N, k: u32) {
+        let mut shifted = N::from_u32(0);
+        shifted.add_shifted(x, k, &mut self.scratch);
+        *x = shifted;
+        self.reduce(x);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reduction_without_division<N: Bignum>() {
+        // 2^61 - 1 and its squares fit a u128
+        let mut m = Mersenne::<N>::new(61);
+        let modulus = (1u128 << 61) - 1;
+        let (mut x, mut expected) = (N::from_u32(3), 3u128);
+        for _ in 0..200 {
+            m.square(&mut x);
+            expected = expected * expected % modulus;
+            assert_eq!(x.low_u64(), expected as u64);
+        }
+        for value in [m.modulus.clone(), N::from_hex("3ffffffffffffffe").unwrap()] {
+            let mut x = value;
+            m.reduce(&mut x);
+            assert!(x.is_zero());
+        }
+        let mut x = N::from_u32(1);
+        m.sub_power_of_two(&mut x, 1);
+        assert_eq!(x.low_u64(), modulus as u64 - 1);
+        // 2^p = 1, also from 0
+        m.sub_power_of_two(&mut x, 61);
+        assert_eq!(x.low_u64(), modulus as u64 - 2);
+        let mut zero = N::from_u32(0);
+        m.sub_power_of_two(&mut zero, 61);
+        assert_eq!(zero.low_u64(), modulus as u64 - 1);
+        // -2 * 2^60 = -2^61 = -1
+        m.mul_power_of_two(&mut x, 60);
+        assert_eq!(x.low_u64(), modulus as u64 - 1);
+    }
+
+    #[cfg(feature = "rug")]
+    #[test]
+    fn rug_reduction() {
+        reduction_without_division::<rug::Integer>();
+    }
+
+    #[cfg(feature = "num-bigint")]
+    #[test]
+    fn num_bigint_reduction() {
+        reduction_without_division::<crate::bignum::BigUint>();
+    }
+}
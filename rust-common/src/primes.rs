@@ -0,0 +1,728 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::bignum::{Bignum, Integer};
+use crate::mersenne::Mersenne;
+
+// The state of a test after `iteration` squarings, all it needs to be resumed.
+// For Lucas-Lehmer that is s_0 = 4, s_(i+1) = s_i^2 - 2 mod M_p; for the PRP
+// test it is x_0 = 3, x_(i+1) = x_i^2 mod M_p.
+// A shifted LL test holds s_i * 2^shift mod M_p instead (see prime_seq).
+// The tests run on any Bignum, the programs on the one chosen by features.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Residue<N = Integer> {
+    pub iteration: u32,
+    pub value: N,
+    pub shift: u32,
+}
+
+impl<N: Bignum> Residue<N> {
+    pub fn start() -> Residue<N> {
+        Residue {
+            iteration: 0,
+            value: N::from_u32(4),
+            shift: 0,
+        }
+    }
+
+    // s_0 = 4 shifted left by `shift` bits, 0 < shift < p
+    pub fn shifted_start(prime: u32, shift: u32) -> Residue<N> {
+        let mut value = N::from_u32(4);
+        Mersenne::new(prime).mul_power_of_two(&mut value, shift);
+        Residue {
+            iteration: 0,
+            value,
+            shift,
+        }
+    }
+
+    // The shift the test started with. It doubles every iteration, so going back
+    // one halves it, which mod p is a multiplication by (p + 1) / 2.
+    pub fn initial_shift(&self, prime: u32) -> u32 {
+        let p = prime as u64;
+        let mut shift = self.shift as u64;
+        let mut half = p / 2 + 1;
+        let mut e = self.iteration;
+        while e > 0 {
+            if e & 1 == 1 {
+                shift = shift * half % p;
+            }
+            half = half * half % p;
+            e >>= 1;
+        }
+        shift as u32
+    }
+}
+
+// A residue mod M_p that is squared over and over, held in whatever form suits
+// the code doing it. It only becomes an N again when asked for.
+pub trait Squarer<N> {
+    fn set(&mut self, value: &N);
+    fn get(&mut self, value: &mut N);
+    // Unreliable when the square cannot be had exactly
+    fn square(&mut self) -> Result<(), Stopped>;
+    // x - 2^k, for k <= p
+    fn sub_power_of_two(&mut self, k: u32);
+}
+
+// The residue as an N all along. `threads` says how many threads a squaring
+// may take right now, which goes up as other tests finish.
+pub struct Plain<'a, N> {
+    m: Mersenne<N>,
+    x: N,
+    threads: &'a dyn Fn() -> usize,
+}
+
+impl<'a, N: Bignum> Plain<'a, N> {
+    pub fn new(prime: u32, threads: &'a dyn Fn() -> usize) -> Plain<'a, N> {
+        Plain {
+            m: Mersenne::new(prime),
+            x: N::from_u32(0),
+            threads,
+        }
+    }
+}
+
+impl<N: Bignum> Squarer<N> for Plain<'_, N> {
+    fn set(&mut self, value: &N) {
+        self.x.clone_from(value);
+    }
+
+    fn get(&mut self, value: &mut N) {
+        value.clone_from(&self.x);
+    }
+
+    fn square(&mut self) -> Result<(), Stopped> {
+        self.m.square_split(&mut self.x, (self.threads)());
+        Ok(())
+    }
+
+    fn sub_power_of_two(&mut self, k: u32) {
+        self.m.sub_power_of_two(&mut self.x, k);
+    }
+}
+
+// Why a test ended without an outcome
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stopped {
+    // Its safe point said so
+    Abandoned,
+    // Its checks failed MAX_ROLLBACKS times in a row
+    Unreliable,
+}
+
+// Consecutive failed checks after which the arithmetic itself is taken to be broken
+const MAX_ROLLBACKS: u32 = 5;
+
+// The in-flight error checks of a run: a Jacobi symbol every `jacobi_interval`
+// LL iterations (0 turns that off) and the Gerbicz check of PRP, which is always
+// on. Failed checks are counted over all workers for the stats.
+#[derive(Default)]
+pub struct ErrorChecks {
+    pub jacobi_interval: u32,
+    failures: AtomicU32,
+}
+
+impl ErrorChecks {
+    pub fn new(jacobi_interval: u32) -> ErrorChecks {
+        ErrorChecks {
+            jacobi_interval,
+            failures: AtomicU32::new(0),
+        }
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    // Logs a failed check and counts it; too many in a row and the test gives up
+    fn failed(
+        &self,
+        check: &str,
+        prime: u32,
+        from: u32,
+        to: u32,
+        rollbacks: u32,
+    ) -> Result<(), Stopped> {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        if rollbacks >= MAX_ROLLBACKS {
+            eprintln!(
+                "{} check failed for M{} {} times in a row, giving up on it",
+                check, prime, rollbacks
+            );
+            return Err(Stopped::Unreliable);
+        }
+        eprintln!(
+            "{} check failed for M{} between iterations {} and {}, rolling back",
+            check, prime, from, to
+        );
+        Ok(())
+    }
+}
+
+// For every k > 0, s_k - 2 is not a square mod M_p: (s_k - 2 | M_p) = -1. A wrong
+// residue has even odds of passing, and the 2^shift of a shifted one does not
+// matter as 2 is a square mod M_p.
+fn jacobi_holds<N: Bignum>(state: &Residue<N>, m: &mut Mersenne<N>) -> bool {
+    let mut x = state.value.clone();
+    m.sub_power_of_two(&mut x, state.shift + 1);
+    x.jacobi(&m.modulus) == -1
+}
+
+// Without checks the residue still comes out of the squarer this often, for
+// the safe point to have a recent state to save
+const UNCHECKED_INTERVAL: u32 = 1000;
+
+// Runs the sequence from `start` up to s_(p-2), checking it every
+// `checks.jacobi_interval` iterations and at the end; a failed check goes back
+// to the last state that passed.
+// A shifted test holds s_i * 2^shift instead. Doubling mod M_p rotates the p
+// bits of a number, so squaring doubles the shift (mod p) and the 2 subtracted
+// becomes 2 * 2^shift. The numbers differ from those of an unshifted test all
+// the way, so an error in the arithmetic is all but certain to give another
+// final residue, which is what makes it a double-check.
+// `safe_point` sees a state after every iteration, along with the number of
+// iterations done; it may block (to pause the test), save it, or return false to
+// abandon the test. The state is the last one taken out of the squarer, which
+// happens at the checks only, so only good states are saved. With checks off
+// it happens every UNCHECKED_INTERVAL iterations instead.
+// `fault` may corrupt a residue as it is checked, for the tests.
+fn prime_seq<N: Bignum>(
+    prime: u32,
+    squarer: &mut dyn Squarer<N>,
+    start: Residue<N>,
+    checks: &ErrorChecks,
+    safe_point: &mut dyn FnMut(&Residue<N>, u32) -> bool,
+    fault: &mut dyn FnMut(u32, &mut N),
+) -> Result<Residue<N>, Stopped> {
+    let mut m = Mersenne::new(prime);
+    let checked = checks.jacobi_interval > 0;
+    let interval = match checked {
+        true => checks.jacobi_interval,
+        false => UNCHECKED_INTERVAL,
+    };
+    let (mut iteration, mut shift) = (start.iteration, start.shift);
+    squarer.set(&start.value);
+    let mut good = start;
+    let mut since_check = 0;
+    let mut rollbacks = 0;
+
+    while iteration + 2 < prime {
+        if shift > 0 {
+            shift = (2 * shift as u64 % prime as u64) as u32;
+        }
+        squarer.square()?;
+        squarer.sub_power_of_two(shift + 1);
+        iteration += 1;
+        since_check += 1;
+
+        if since_check == interval || iteration + 2 == prime {
+            since_check = 0;
+            let mut state = Residue {
+                iteration,
+                value: N::from_u32(0),
+                shift,
+            };
+            squarer.get(&mut state.value);
+            fault(iteration, &mut state.value);
+            if !checked || jacobi_holds(&state, &mut m) {
+                good = state;
+                rollbacks = 0;
+            } else {
+                rollbacks += 1;
+                checks.failed("Jacobi", prime, good.iteration, iteration, rollbacks)?;
+                iteration = good.iteration;
+                shift = good.shift;
+                squarer.set(&good.value);
+            }
+        }
+        if !safe_point(&good, iteration) {
+            return Err(Stopped::Abandoned);
+        }
+    }
+    Ok(good)
+}
+
+// A finished test: M_p is prime iff the last residue is 0. The low 64 bits of that
+// residue ("res64") are what another run of the same test has to reproduce.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Outcome {
+    pub is_prime: bool,
+    pub res64: u64,
+}
+
+pub fn lucas_lehmer<N: Bignum>(
+    prime: u32,
+    start: Residue<N>,
+    squarer: &mut dyn Squarer<N>,
+    checks: &ErrorChecks,
+    safe_point: &mut dyn FnMut(&Residue<N>, u32) -> bool,
+) -> Result<Outcome, Stopped> {
+    lucas_lehmer_with_faults(prime, start, squarer, checks, safe_point, &mut |_, _| {})
+}
+
+fn lucas_lehmer_with_faults<N: Bignum>(
+    prime: u32,
+    start: Residue<N>,
+    squarer: &mut dyn Squarer<N>,
+    checks: &ErrorChecks,
+    safe_point: &mut dyn FnMut(&Residue<N>, u32) -> bool,
+    fault: &mut dyn FnMut(u32, &mut N),
+) -> Result<Outcome, Stopped> {
+    let state = prime_seq(prime, squarer, start, checks, safe_point, fault)?;
+    // 2^p = 1 mod M_p, so shifting by p - shift more undoes the shift
+    let mut s = state.value;
+    if state.shift > 0 {
+        Mersenne::new(prime).mul_power_of_two(&mut s, prime - state.shift);
+    }
+    Ok(Outcome {
+        is_prime: s.is_zero(),
+        res64: s.low_u64(),
+    })
+}
+
+// Gerbicz-Li checks cover blocks of L iterations and take L extra squarings,
+// done once every L^2 iterations. That is also how often there is a checked
+// state to save, so a test has at least 16 of them.
+fn gerbicz_block(prime: u32) -> u32 {
+    let mut block = 2;
+    while block < 1000 && 16 * (block + 1) * (block + 1) <= prime {
+        block += 1;
+    }
+    block
+}
+
+fn square_times<N: Bignum>(value: &mut N, m: &mut Mersenne<N>, times: u32) {
+    for _ in 0..times {
+        m.square(value);
+    }
+}
+
+// Fermat probable prime test to base 3: M_p is a PRP iff 3^(M_p + 1) = 3^(2^p) = 9.
+// Unlike LL it can check itself: with u_j the residue after j blocks of L squarings
+// and d the product u_0 * ... * u_n, d = u_0 * (d / u_n)^(2^L) whenever every u_j
+// is right. A check failing means some residue went wrong, and the test rolls back
+// to the last state that passed one. Only checked states go to `safe_point`,
+// together with the iteration actually reached.
+// The squarer carries x; the products and the check, a small share of the
+// work, are done on Ns, so x is only taken out at the end of each block.
+pub fn prp<N: Bignum>(
+    prime: u32,
+    start: Residue<N>,
+    squarer: &mut dyn Squarer<N>,
+    checks: &ErrorChecks,
+    safe_point: &mut dyn FnMut(&Residue<N>, u32) -> bool,
+) -> Result<Outcome, Stopped> {
+    prp_with_faults(prime, start, squarer, checks, safe_point, &mut |_, _| {})
+}
+
+// `fault` may corrupt x as it is taken out of the squarer, for the tests
+fn prp_with_faults<N: Bignum>(
+    prime: u32,
+    mut verified: Residue<N>,
+    squarer: &mut dyn Squarer<N>,
+    checks: &ErrorChecks,
+    safe_point: &mut dyn FnMut(&Residue<N>, u32) -> bool,
+    fault: &mut dyn FnMut(u32, &mut N),
+) -> Result<Outcome, Stopped> {
+    let mut m = Mersenne::new(prime);
+    let block = gerbicz_block(prime);
+    let mut rollbacks = 0;
+
+    while verified.iteration < prime {
+        let first = verified.iteration;
+        let end = (first + block * block).min(prime);
+        squarer.set(&verified.value);
+        let mut x = verified.value.clone();
+        // The product up to the latest full block, the one before it, and that block's residue
+        let mut product = x.clone();
+        let mut previous = x.clone();
+        let mut last_block = (first, x.clone());
+        let mut in_block = 0;
+
+        for iteration in first..end {
+            squarer.square()?;
+            if !safe_point(&verified, iteration + 1) {
+                return Err(Stopped::Abandoned);
+            }
+            in_block += 1;
+            if in_block == block || iteration + 1 == end {
+                squarer.get(&mut x);
+                fault(iteration + 1, &mut x);
+                // Back in, so that a corrupted x goes on being squared
+                squarer.set(&x);
+            }
+            if in_block == block {
+                in_block = 0;
+                previous = product.clone();
+                product.mul(&x);
+                m.reduce(&mut product);
+                last_block = (iteration + 1, x.clone());
+            }
+        }
+
+        let mut check = previous;
+        square_times(&mut check, &mut m, block);
+        check.mul(&verified.value);
+        m.reduce(&mut check);
+        let blocks_ok = last_block.0 == first || check == product;
+        // The last round may end part way into a block, that part is simply done twice
+        let (tail_start, mut tail) = last_block;
+        square_times(&mut tail, &mut m, end - tail_start);
+
+        if blocks_ok && tail == x {
+            verified = Residue {
+                iteration: end,
+                value: x,
+                shift: 0,
+            };
+            rollbacks = 0;
+        } else {
+            rollbacks += 1;
+            checks.failed("Gerbicz", prime, first, end, rollbacks)?;
+        }
+    }
+
+    // 9 itself for all but the smallest M_p
+    let mut nine = N::from_u32(9);
+    m.reduce(&mut nine);
+    Ok(Outcome {
+        is_prime: verified.value == nine,
+        res64: verified.value.low_u64(),
+    })
+}
+
+// The primality test to run, chosen with --test
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Test {
+    LucasLehmer,
+    Prp,
+}
+
+impl Test {
+    pub fn parse(name: &str) -> Result<Test, String> {
+        match name.to_lowercase().as_str() {
+            "ll" => Ok(Test::LucasLehmer),
+            "prp" => Ok(Test::Prp),
+            _ => Err(format!("unknown test {}, expected ll or prp", name)),
+        }
+    }
+
+    // As written to the ledger
+    pub fn name(self) -> &'static str {
+        match self {
+            Test::LucasLehmer => "LL",
+            Test::Prp => "PRP",
+        }
+    }
+
+    pub fn start<N: Bignum>(self) -> Residue<N> {
+        match self {
+            Test::LucasLehmer => Residue::start(),
+            Test::Prp => Residue {
+                iteration: 0,
+                value: N::from_u32(3),
+                shift: 0,
+            },
+        }
+    }
+
+    pub fn iterations(self, prime: u32) -> u32 {
+        match self {
+            Test::LucasLehmer => prime.saturating_sub(2),
+            Test::Prp => prime,
+        }
+    }
+
+    pub fn run<N: Bignum>(
+        self,
+        prime: u32,
+        start: Residue<N>,
+        squarer: &mut dyn Squarer<N>,
+        checks: &ErrorChecks,
+        safe_point: &mut dyn FnMut(&Residue<N>, u32) -> bool,
+    ) -> Result<Outcome, Stopped> {
+        match self {
+            Test::LucasLehmer => lucas_lehmer(prime, start, squarer, checks, safe_point),
+            Test::Prp => prp(prime, start, squarer, checks, safe_point),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPONENTS: [u32; 15] = [3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 61, 67, 89, 107, 127];
+    const MERSENNE: [u32; 11] = [3, 5, 7, 13, 17, 19, 31, 61, 89, 107, 127];
+
+    // A test of M_p on one thread, from `start`
+    fn run(
+        test: Test,
+        prime: u32,
+        start: Residue,
+        checks: &ErrorChecks,
+        safe_point: &mut dyn FnMut(&Residue, u32) -> bool,
+    ) -> Result<Outcome, Stopped> {
+        test.run(
+            prime,
+            start,
+            &mut Plain::new(prime, &|| 1),
+            checks,
+            safe_point,
+        )
+    }
+
+    fn ll(prime: u32, start: Residue, checks: &ErrorChecks) -> Outcome {
+        run(Test::LucasLehmer, prime, start, checks, &mut |_, _| true).unwrap()
+    }
+
+    #[test]
+    fn mersenne_exponents_below_130() {
+        let checks = ErrorChecks::new(10);
+        let found: Vec<u32> = EXPONENTS
+            .iter()
+            .copied()
+            .filter(|&p| ll(p, Residue::start(), &checks).is_prime)
+            .collect();
+        assert_eq!(found, MERSENNE);
+        // s_9 mod M_11 = 1736 (M_11 = 23 * 89)
+        let outcome = ll(11, Residue::start(), &checks);
+        assert_eq!(
+            outcome,
+            Outcome {
+                is_prime: false,
+                res64: 1736
+            }
+        );
+        assert_eq!(checks.failures(), 0);
+    }
+
+    #[test]
+    fn safe_points_count_every_iteration() {
+        for test in [Test::LucasLehmer, Test::Prp] {
+            let mut seen = vec![];
+            let result = run(
+                test,
+                127,
+                test.start(),
+                &ErrorChecks::default(),
+                &mut |_, iteration| {
+                    seen.push(iteration);
+                    true
+                },
+            );
+            assert!(result.unwrap().is_prime);
+            assert_eq!(seen, (1..=test.iterations(127)).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn resumed_from_saved_state() {
+        let checks = ErrorChecks::new(20);
+        let mut saved = None;
+        let result = run(
+            Test::LucasLehmer,
+            127,
+            Residue::start(),
+            &checks,
+            &mut |state, _| {
+                if state.iteration == 60 {
+                    saved = Some(state.clone());
+                    return false;
+                }
+                true
+            },
+        );
+        assert_eq!(result, Err(Stopped::Abandoned));
+
+        let resumed = run(
+            Test::LucasLehmer,
+            127,
+            saved.unwrap(),
+            &checks,
+            &mut |state, _| {
+                assert!(state.iteration >= 60);
+                true
+            },
+        );
+        assert_eq!(resumed, Ok(ll(127, Residue::start(), &checks)));
+        assert!(resumed.unwrap().is_prime);
+    }
+
+    #[test]
+    fn shifted_runs_give_the_same_res64() {
+        // The Jacobi check holds whatever the shift
+        let checks = ErrorChecks::new(7);
+        for &p in &EXPONENTS {
+            let outcome = ll(p, Residue::start(), &checks);
+            for shift in 1..p {
+                let shifted = ll(p, Residue::shifted_start(p, shift), &checks);
+                assert_eq!(shifted, outcome, "M{} shifted by {}", p, shift);
+            }
+        }
+        assert_eq!(checks.failures(), 0);
+
+        let start = Residue::shifted_start(127, 5);
+        run(Test::LucasLehmer, 127, start, &checks, &mut |state, _| {
+            assert_eq!(state.initial_shift(127), 5);
+            true
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn jacobi_check_rolls_back_from_bad_residues() {
+        let checks = ErrorChecks::new(100);
+        let clean = ll(1279, Residue::start(), &checks);
+
+        // 3 is not a square mod M_p, so 3 (s - 2) + 2 always fails the check
+        let mut flipped = false;
+        let mut fault = |iteration: u32, s: &mut Integer| {
+            if iteration == 600 && !flipped {
+                flipped = true;
+                let mut m = Mersenne::new(1279);
+                m.sub_power_of_two(s, 1);
+                Bignum::mul(s, &Integer::from_u32(3));
+                m.reduce(s);
+                s.add_shifted(&Integer::from_u32(2), 0, &mut Integer::from_u32(0));
+            }
+        };
+        let mut saved = vec![];
+        let mut safe_point = |state: &Residue, _: u32| {
+            saved.push(state.iteration);
+            true
+        };
+        let mut squarer = Plain::new(1279, &|| 1);
+        let outcome = lucas_lehmer_with_faults(
+            1279,
+            Residue::start(),
+            &mut squarer,
+            &checks,
+            &mut safe_point,
+            &mut fault,
+        );
+        assert_eq!(outcome, Ok(clean));
+        assert_eq!(checks.failures(), 1);
+        // States are checked every 100 iterations and after the last one
+        assert!(saved
+            .iter()
+            .all(|&iteration| iteration % 100 == 0 || iteration == 1277));
+    }
+
+    #[test]
+    fn prp_agrees_with_lucas_lehmer() {
+        let checks = ErrorChecks::default();
+        let prp = |p| run(Test::Prp, p, Test::Prp.start(), &checks, &mut |_, _| true).unwrap();
+        let found: Vec<u32> = EXPONENTS
+            .iter()
+            .copied()
+            .filter(|&p| prp(p).is_prime)
+            .collect();
+        assert_eq!(found, MERSENNE);
+        // 3^(2^11) mod 2047
+        assert_eq!(prp(11).res64, 929);
+        assert_eq!(Test::parse("PRP"), Ok(Test::Prp));
+    }
+
+    #[test]
+    fn prp_rolls_back_from_bad_residues() {
+        let checks = ErrorChecks::default();
+        let clean = run(Test::Prp, 1279, Test::Prp.start(), &checks, &mut |_, _| {
+            true
+        });
+
+        // One bit flipped at the end of a full block, one at the end of the
+        // last round, part way into a block
+        for &bad in &[504, 1279] {
+            let mut flipped = false;
+            let mut fault = |iteration: u32, x: &mut Integer| {
+                if iteration == bad && !flipped {
+                    flipped = true;
+                    x.add_shifted(&Integer::from_u32(1), 0, &mut Integer::from_u32(0));
+                }
+            };
+            let mut saved = vec![];
+            let mut safe_point = |state: &Residue, _: u32| {
+                saved.push(state.iteration);
+                true
+            };
+            let mut squarer = Plain::new(1279, &|| 1);
+            let start = Test::Prp.start();
+            let outcome = prp_with_faults(
+                1279,
+                start,
+                &mut squarer,
+                &checks,
+                &mut safe_point,
+                &mut fault,
+            );
+            assert!(flipped);
+            assert_eq!(outcome, clean);
+            // Only checked states are handed out for saving
+            let round = gerbicz_block(1279).pow(2);
+            assert!(saved.iter().all(|&iteration| iteration % round == 0));
+        }
+        assert_eq!(checks.failures(), 2);
+    }
+
+    #[test]
+    fn gives_up_when_checks_keep_failing() {
+        let checks = ErrorChecks::new(100);
+        let mut squarer = Plain::new(1279, &|| 1);
+        let mut bad = |_: u32, x: &mut Integer| *x = Integer::from_u32(2);
+        let outcome = lucas_lehmer_with_faults(
+            1279,
+            Residue::start(),
+            &mut squarer,
+            &checks,
+            &mut |_, _| true,
+            &mut bad,
+        );
+        assert_eq!(outcome, Err(Stopped::Unreliable));
+        assert_eq!(checks.failures(), MAX_ROLLBACKS);
+
+        let mut bad = |_: u32, x: &mut Integer| {
+            x.add_shifted(&Integer::from_u32(1), 0, &mut Integer::from_u32(0))
+        };
+        let outcome = prp_with_faults(
+            1279,
+            Test::Prp.start(),
+            &mut squarer,
+            &checks,
+            &mut |_, _| true,
+            &mut bad,
+        );
+        assert_eq!(outcome, Err(Stopped::Unreliable));
+        assert_eq!(checks.failures(), 2 * MAX_ROLLBACKS);
+    }
+
+    #[cfg(all(feature = "rug", feature = "num-bigint"))]
+    fn outcomes<N: Bignum>(p: u32) -> (Outcome, Outcome) {
+        let checks = ErrorChecks::new(50);
+        let mut squarer = Plain::<N>::new(p, &|| 1);
+        let ll = lucas_lehmer(
+            p,
+            Residue::shifted_start(p, p / 2),
+            &mut squarer,
+            &checks,
+            &mut |_, _| true,
+        );
+        let prp = prp(p, Test::Prp.start(), &mut squarer, &checks, &mut |_, _| {
+            true
+        });
+        (ll.unwrap(), prp.unwrap())
+    }
+
+    #[cfg(all(feature = "rug", feature = "num-bigint"))]
+    #[test]
+    fn backends_agree() {
+        for &p in &[3, 11, 31, 61, 89, 521, 607, 1279] {
+            assert_eq!(
+                outcomes::<rug::Integer>(p),
+                outcomes::<crate::bignum::BigUint>(p),
+                "M{}",
+                p
+            );
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::channel;
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::bignum::Bignum;
+
+// Squaring one big residue on several threads, for the last tests of a run.
+//
+// With x = a + b 2^w and a < 2^w,
+//
+//   x^2 = a^2 + ((a + b)^2 - a^2 - b^2) 2^w + b^2 2^(2w),
+//
+// three squarings of half the size that can run on different threads. Each
+// level of splitting makes three times as many for the threads to share. On
+// one core they run one after another and the split is slower than a plain
+// squaring (the table in the README); how much faster it gets with a core per
+// squaring has not been measured yet.
+//
+// The squarings go to helper threads that are started once and wait on a
+// condition variable in between, so a split costs a wake-up, not a spawn.
+// Whether that wins over one thread squaring alone depends on the machine, the
+// backend, the size of x and how many threads take part, so it is measured
+// rather than assumed: see `Timings`.
+
+// Smaller numbers are never split. Below 2^15 bits GMP squares in under 50us,
+// and on one core the split makes that 10 to 35% longer before any thread is
+// woken; `split_speedup` below gives the figures for a given machine.
+const MIN_SPLIT_BITS: u32 = 1 << 15;
+
+// The first squarings of each size and thread count that alternate between
+// split and plain ones, after which the faster kind is kept
+const TRIALS: u32 = 16;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+struct Pool {
+    // Tasks not yet taken and the number of helpers started so far
+    queue: Mutex<(VecDeque<Task>, usize)>,
+    work: Condvar,
+}
+
+impl Pool {
+    fn get() -> &'static Pool {
+        static POOL: OnceLock<Pool> = OnceLock::new();
+        POOL.get_or_init(|| Pool {
+            queue: Mutex::new((VecDeque::new(), 0)),
+            work: Condvar::new(),
+        })
+    }
+
+    // Hands out `tasks`, first making sure there are `helpers` threads to take them
+    fn push(&'static self, tasks: Vec<Task>, helpers: usize) {
+        let mut queue = self.queue.lock().unwrap();
+        while queue.1 < helpers {
+            queue.1 += 1;
+            thread::spawn(move || self.help());
+        }
+        queue.0.extend(tasks);
+        self.work.notify_all();
+    }
+
+    fn try_pop(&self) -> Option<Task> {
+        self.queue.lock().unwrap().0.pop_front()
+    }
+
+    fn help(&self) {
+        loop {
+            let task = {
+                let mut queue = self.queue.lock().unwrap();
+                loop {
+                    match queue.0.pop_front() {
+                        Some(task) => break task,
+                        None => queue = self.work.wait(queue).unwrap(),
+                    }
+                }
+            };
+            task();
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Trial {
+    plain: Duration,
+    split: Duration,
+    count: u32,
+}
+
+// How long plain and split squarings took, per (log2 of the bits, threads)
+struct Timings(Mutex<HashMap<(u32, usize), Trial>>);
+
+impl Timings {
+    fn get() -> &'static Timings {
+        static TIMINGS: OnceLock<Timings> = OnceLock::new();
+        TIMINGS.get_or_init(|| Timings(Mutex::new(HashMap::new())))
+    }
+
+    // Whether to split the next squaring, and whether it is one of the trials to time
+    fn next(&self, key: (u32, usize)) -> (bool, bool) {
+        let trial = self
+            .0
+            .lock()
+            .unwrap()
+            .get(&key)
+            .copied()
+            .unwrap_or_default();
+        match trial.count >= TRIALS {
+            true => (trial.split < trial.plain, false),
+            false => (trial.count % 2 == 1, true),
+        }
+    }
+
+    fn record(&self, key: (u32, usize), split: bool, took: Duration) {
+        let mut timings = self.0.lock().unwrap();
+        let trial = timings.entry(key).or_default();
+        match split {
+            true => trial.split += took,
+            false => trial.plain += took,
+        }
+        trial.count += 1;
+    }
+}
+
+// How many times to halve x for `threads`: 3^levels squarings, at least one per thread
+fn levels(threads: usize) -> u32 {
+    let mut levels = 1;
+    while 3usize.pow(levels) < threads {
+        levels += 1;
+    }
+    levels
+}
+
+// x^2 on up to `threads` threads, if that has proved faster than on one
+pub fn split_square<N: Bignum>(x: &mut N, threads: usize) {
+    let bits = x.significant_bits();
+    if threads < 2 || bits < MIN_SPLIT_BITS {
+        x.square();
+        return;
+    }
+    let key = (32 - bits.leading_zeros(), threads);
+    let timings = Timings::get();
+    let (split, timed) = timings.next(key);
+    let start = Instant::now();
+    match split {
+        true => square_on(x, threads),
+        false => x.square(),
+    }
+    if timed {
+        timings.record(key, split, start.elapsed());
+    }
+}
+
+// x^2, always split
+fn square_on<N: Bignum>(x: &mut N, threads: usize) {
+    let levels = levels(threads);
+    let mut parts = vec![];
+    let mut widths = vec![];
+    let mut scratch = N::from_u32(0);
+    cut(
+        std::mem::replace(x, N::from_u32(0)),
+        levels,
+        &mut parts,
+        &mut widths,
+        &mut scratch,
+    );
+
+    // The tasks go to the helpers, and this thread takes what they have not
+    // started yet, so a squaring never waits for a busy helper
+    let (done, results) = channel();
+    let count = parts.len();
+    let tasks: Vec<Task> = parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, mut part)| {
+            let done = done.clone();
+            Box::new(move || {
+                part.square();
+                let _ = done.send((i, part));
+            }) as Task
+        })
+        .collect();
+    let pool = Pool::get();
+    pool.push(tasks, threads - 1);
+    let mut squares: Vec<Option<N>> = vec![None; count];
+    for _ in 0..count {
+        let (i, square) = loop {
+            if let Ok(result) = results.try_recv() {
+                break result;
+            }
+            match pool.try_pop() {
+                Some(task) => task(),
+                None => break results.recv().unwrap(),
+            }
+        };
+        squares[i] = Some(square);
+    }
+
+    let mut squares = squares.into_iter().map(Option::unwrap);
+    *x = join(&mut squares, &mut widths.into_iter(), levels, &mut scratch);
+}
+
+// x as a, a + b and b, `levels` times over, with the widths w in the same order
+fn cut<N: Bignum>(x: N, levels: u32, parts: &mut Vec<N>, widths: &mut Vec<u32>, scratch: &mut N) {
+    if levels == 0 {
+        parts.push(x);
+        return;
+    }
+    let bits = x.significant_bits();
+    let w = bits.div_ceil(2);
+    let (a, b) = (x.bit_slice(0, w), x.bit_slice(w, bits - w));
+    let mut sum = x.bit_slice(0, w);
+    sum.add_shifted(&b, 0, scratch);
+    widths.push(w);
+    cut(a, levels - 1, parts, widths, scratch);
+    cut(sum, levels - 1, parts, widths, scratch);
+    cut(b, levels - 1, parts, widths, scratch);
+}
+
+// The squares of what `cut` made put back together into x^2
+fn join<N: Bignum>(
+    squares: &mut dyn Iterator<Item = N>,
+    widths: &mut dyn Iterator<Item = u32>,
+    levels: u32,
+    scratch: &mut N,
+) -> N {
+    if levels == 0 {
+        return squares.next().unwrap();
+    }
+    let w = widths.next().unwrap();
+    let a = join(squares, widths, levels - 1, scratch);
+    let mut middle = join(squares, widths, levels - 1, scratch);
+    let b = join(squares, widths, levels - 1, scratch);
+    middle.sub(&a);
+    middle.sub(&b);
+    let mut x = a;
+    x.add_shifted(&middle, w, scratch);
+    x.add_shifted(&b, 2 * w, scratch);
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bignum::Integer;
+
+    fn split_squares_are_squares<N: Bignum>() {
+        let m = N::mersenne(110503);
+        for threads in [2, 3, 4, 9, 10] {
+            let mut x = m.clone();
+            x.sub_power_of_two(12345, &m, &mut N::from_u32(0));
+            let mut expected = x.clone();
+            expected.square();
+            square_on(&mut x, threads);
+            assert_eq!(x, expected, "{} threads", threads);
+            // Whatever the timings say
+            split_square(&mut x, threads);
+            expected.square();
+            assert_eq!(x, expected, "{} threads", threads);
+        }
+        assert_eq!(
+            (levels(2), levels(3), levels(4), levels(9), levels(10)),
+            (1, 1, 2, 2, 3)
+        );
+    }
+
+    #[cfg(feature = "rug")]
+    #[test]
+    fn rug_split_squares() {
+        split_squares_are_squares::<rug::Integer>();
+    }
+
+    #[cfg(feature = "num-bigint")]
+    #[test]
+    fn num_bigint_split_squares() {
+        split_squares_are_squares::<crate::bignum::BigUint>();
+    }
+
+    // Plain against split squarings of the backend in use, the numbers the
+    // thresholds above come from:
+    //
+    //   cd rust-common && cargo test --release split_speedup -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn split_speedup() {
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        println!("{} cores", cores);
+        for log in 14..=24 {
+            let p = (1 << log) - 1;
+            let m = Integer::mersenne(p);
+            let mut x = m.clone();
+            x.sub_power_of_two(p / 3, &m, &mut Integer::from_u32(0));
+            let rounds = (1u32 << (24 - log)).max(4);
+            let time = |threads: usize| {
+                let start = Instant::now();
+                for _ in 0..rounds {
+                    let mut y = x.clone();
+                    match threads {
+                        1 => Bignum::square(&mut y),
+                        _ => square_on(&mut y, threads),
+                    }
+                }
+                start.elapsed() / rounds
+            };
+            let plain = time(1);
+            print!("2^{} bits: {:?} plain", log, plain);
+            for threads in [2, 3, 4, 9] {
+                let split = time(threads);
+                print!(
+                    ", {} threads {:.2}x",
+                    threads,
+                    plain.as_secs_f64() / split.as_secs_f64()
+                );
+            }
+            println!();
+        }
+    }
+}
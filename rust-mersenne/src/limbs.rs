@@ -0,0 +1,275 @@
+use common::primes::Outcome;
+
+// Lucas-Lehmer for small exponents in fixed-width arithmetic on the stack. For
+// numbers this short a heap allocated big integer spends more time being one
+// than squaring, so p < 64 runs in a u128 and the larger ones in N 64-bit
+// limbs, N a power of two with 64 N > p, which makes room for the sum of the
+// two folds of a reduction.
+
+// The widest exponent done here, from `limbs_speedup` below on one core. GMP
+// takes 2.4 times as long for M509 in 8 limbs, but M607 and M1021 in 16 limbs
+// are 1.1 to 1.6 times as fast with it. num-bigint still takes 1.0 to 1.9
+// times as long as 16 limbs, at 1279 the O(N^2) squaring falls behind it.
+#[cfg(feature = "rug")]
+const MAX_PRIME: u32 = 511;
+#[cfg(not(feature = "rug"))]
+const MAX_PRIME: u32 = 1023;
+
+// Runs s from `start` (s_iteration, limbs least significant first) up to
+// s_(p-2). None if p is too big for a fast path.
+pub fn lucas_lehmer(prime: u32, iteration: u32, start: &[u64]) -> Option<Outcome> {
+    let s = match prime {
+        _ if prime > MAX_PRIME => return None,
+        0..=63 => vec![u128_seq(prime, iteration, start.first().copied().unwrap_or(0))],
+        64..=127 => fixed_seq::<2>(prime, iteration, start),
+        128..=255 => fixed_seq::<4>(prime, iteration, start),
+        256..=511 => fixed_seq::<8>(prime, iteration, start),
+        _ => fixed_seq::<16>(prime, iteration, start),
+    };
+    Some(Outcome {
+        is_prime: s.iter().all(|&limb| limb == 0),
+        res64: s[0],
+    })
+}
+
+// s^2 of a 63 bit s fits a u128, and two folds take it below 2^p
+fn u128_seq(prime: u32, mut iteration: u32, mut s: u64) -> u64 {
+    let m = (1u64 << prime) - 1;
+    while iteration + 2 < prime {
+        let square = s as u128 * s as u128;
+        let r = (square & m as u128) as u64 + (square >> prime) as u64;
+        s = (r & m) + (r >> prime);
+        if s == m {
+            s = 0;
+        }
+        s = if s < 2 { s + m - 2 } else { s - 2 };
+        iteration += 1;
+    }
+    s
+}
+
+fn fixed_seq<const N: usize>(prime: u32, mut iteration: u32, start: &[u64]) -> Vec<u64> {
+    let mut m = [0u64; N];
+    for (i, limb) in m.iter_mut().enumerate() {
+        *limb = limb_below(&[u64::MAX; N], prime, i);
+    }
+    let mut s = [0u64; N];
+    s[..start.len()].copy_from_slice(start);
+    let mut square = [[0u64; N]; 2];
+    while iteration + 2 < prime {
+        square_into(&s, &mut square);
+        s = reduce(&square, prime, &m);
+        sub_two(&mut s, &m);
+        iteration += 1;
+    }
+    s.to_vec()
+}
+
+// Schoolbook, with the 2N limbs of the square in two halves. Each cross
+// product x_i x_j turns up twice, so it is worked out once and doubled.
+fn square_into<const N: usize>(x: &[u64; N], square: &mut [[u64; N]; 2]) {
+    let out = square.as_flattened_mut();
+    out.fill(0);
+    for i in 0..N {
+        let mut carry = 0u128;
+        for j in i + 1..N {
+            let t = x[i] as u128 * x[j] as u128 + out[i + j] as u128 + carry;
+            out[i + j] = t as u64;
+            carry = t >> 64;
+        }
+        out[i + N] = carry as u64;
+    }
+
+    let mut high = 0;
+    for limb in out.iter_mut() {
+        let doubled = (*limb << 1) | high;
+        high = *limb >> 63;
+        *limb = doubled;
+    }
+
+    let mut carry = 0u128;
+    for (i, &limb) in x.iter().enumerate() {
+        let diagonal = limb as u128 * limb as u128;
+        let t = out[2 * i] as u128 + (diagonal as u64) as u128 + carry;
+        out[2 * i] = t as u64;
+        let t = out[2 * i + 1] as u128 + (diagonal >> 64) + (t >> 64);
+        out[2 * i + 1] = t as u64;
+        carry = t >> 64;
+    }
+}
+
+// Limb i of x >> shift
+fn limb_above(x: &[u64], shift: u32, i: usize) -> u64 {
+    let (skip, bits) = ((shift / 64) as usize, shift % 64);
+    let limb = |k: usize| x.get(k).copied().unwrap_or(0);
+    match bits {
+        0 => limb(skip + i),
+        _ => (limb(skip + i) >> bits) | (limb(skip + i + 1) << (64 - bits)),
+    }
+}
+
+// Limb i of x & (2^bits - 1)
+fn limb_below(x: &[u64], bits: u32, i: usize) -> u64 {
+    let start = 64 * i as u32;
+    match bits.saturating_sub(start) {
+        0 => 0,
+        b if b >= 64 => x[i],
+        b => x[i] & ((1 << b) - 1),
+    }
+}
+
+// x mod M_p of x < 2^(2p): the low p bits plus the high ones are below 2^(p+1),
+// and adding the one bit left over at p back in brings that below 2^p
+fn reduce<const N: usize>(square: &[[u64; N]; 2], prime: u32, m: &[u64; N]) -> [u64; N] {
+    let wide = square.as_flattened();
+    let mut r = [0u64; N];
+    let mut carry = false;
+    for (i, limb) in r.iter_mut().enumerate() {
+        let (sum, c1) = limb_below(wide, prime, i).overflowing_add(limb_above(wide, prime, i));
+        let (sum, c2) = sum.overflowing_add(carry as u64);
+        *limb = sum;
+        carry = c1 || c2;
+    }
+
+    let (sum, top) = (r, limb_above(&r, prime, 0));
+    for (i, limb) in r.iter_mut().enumerate() {
+        *limb = limb_below(&sum, prime, i);
+    }
+    add_small(&mut r, top);
+    // M_p itself is 0
+    if r == *m {
+        r = [0; N];
+    }
+    r
+}
+
+fn add_small(x: &mut [u64], mut carry: u64) {
+    for limb in x.iter_mut() {
+        if carry == 0 {
+            break;
+        }
+        let (sum, overflow) = limb.overflowing_add(carry);
+        *limb = sum;
+        carry = overflow as u64;
+    }
+}
+
+// x - 2 mod M_p for reduced x; below 2 that is M_p - (2 - x), and M_p has all
+// of its low limb set
+fn sub_two<const N: usize>(x: &mut [u64; N], m: &[u64; N]) {
+    if x[0] < 2 && x[1..].iter().all(|&limb| limb == 0) {
+        let short = 2 - x[0];
+        *x = *m;
+        x[0] -= short;
+        return;
+    }
+    let mut borrow = 2;
+    for limb in x.iter_mut() {
+        let (diff, under) = limb.overflowing_sub(borrow);
+        *limb = diff;
+        borrow = under as u64;
+        if borrow == 0 {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::bignum::{Bignum, Integer};
+    use common::primes::{self, ErrorChecks, Plain, Residue};
+
+    fn lucas_lehmer_of(
+        p: u32,
+        checks: &ErrorChecks,
+        safe_point: &mut dyn FnMut(&Residue, u32) -> bool,
+    ) -> Outcome {
+        let mut squarer = Plain::<Integer>::new(p, &|| 1);
+        primes::lucas_lehmer(p, Residue::start(), &mut squarer, checks, safe_point).unwrap()
+    }
+
+    #[test]
+    fn agrees_with_big_integers() {
+        for &p in crate::generate_primes(MAX_PRIME).iter() {
+            let generic = lucas_lehmer_of(p, &ErrorChecks::default(), &mut |_, _| true);
+            assert_eq!(lucas_lehmer(p, 0, &[4]), Some(generic), "M{}", p);
+        }
+        assert_eq!(lucas_lehmer(MAX_PRIME + 2, 0, &[4]), None);
+    }
+
+    #[test]
+    fn resumes_part_way() {
+        for &p in &[61, 127, 509] {
+            let mut halfway = None;
+            // Checked every iteration, so that every state reaches the safe point
+            let outcome = lucas_lehmer_of(p, &ErrorChecks::new(1), &mut |state, _| {
+                if state.iteration == p / 2 {
+                    halfway = Some(state.clone());
+                }
+                true
+            });
+            let halfway = halfway.unwrap();
+            let limbs = halfway.value.to_limbs();
+            assert_eq!(lucas_lehmer(p, halfway.iteration, &limbs), Some(outcome));
+        }
+    }
+
+    #[test]
+    fn wraps_around_the_modulus() {
+        let m = [u64::MAX, (1 << 25) - 1];
+        let mut x = [1, 0];
+        sub_two(&mut x, &m);
+        assert_eq!(x, [u64::MAX - 1, (1 << 25) - 1]);
+        sub_two(&mut x, &m);
+        assert_eq!(x, [u64::MAX - 3, (1 << 25) - 1]);
+
+        // M_p^2 and (M_p - 1)^2 = 1
+        for (x, expected) in [(m, [0, 0]), ([u64::MAX - 1, (1 << 25) - 1], [1, 0])] {
+            let mut square = [[0; 2]; 2];
+            square_into(&x, &mut square);
+            assert_eq!(reduce(&square, 89, &m), expected);
+        }
+    }
+
+    // The fixed widths against the big integer backend in use, past MAX_PRIME
+    // too, the numbers MAX_PRIME comes from:
+    //
+    //   cargo test --release limbs_speedup -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn limbs_speedup() {
+        // The best of a few rounds
+        let time = |run: &dyn Fn()| {
+            (0..10)
+                .map(|_| {
+                    let start = std::time::Instant::now();
+                    run();
+                    start.elapsed()
+                })
+                .min()
+                .unwrap()
+        };
+        for &p in &[127, 251, 509, 607, 1021, 1279, 2039, 4093] {
+            let fixed = match p {
+                0..=127 => time(&|| drop(fixed_seq::<2>(p, 0, &[4]))),
+                128..=255 => time(&|| drop(fixed_seq::<4>(p, 0, &[4]))),
+                256..=511 => time(&|| drop(fixed_seq::<8>(p, 0, &[4]))),
+                512..=1023 => time(&|| drop(fixed_seq::<16>(p, 0, &[4]))),
+                1024..=2047 => time(&|| drop(fixed_seq::<32>(p, 0, &[4]))),
+                _ => time(&|| drop(fixed_seq::<64>(p, 0, &[4]))),
+            };
+            let checks = ErrorChecks::default();
+            let generic = time(&|| {
+                lucas_lehmer_of(p, &checks, &mut |_, _| true);
+            });
+            println!(
+                "M{}: {:?} in limbs, {:?} generic, {:.2}x",
+                p,
+                fixed,
+                generic,
+                generic.as_secs_f64() / fixed.as_secs_f64()
+            );
+        }
+    }
+}
@@ -1,10 +1,20 @@
-use rug::Integer;
-use std::env;
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
 use std::thread;
 
+mod limbs;
+mod schedule;
+use common::args::{self, get_flag, get_setting};
+use common::bignum::Bignum;
+use common::checkpoint::Checkpoints;
+use common::factor::{self, Factoring};
+use common::ledger::{Ledger, Record};
+use common::primes::{ErrorChecks, Outcome, Plain, Residue, Stopped, Test};
+use schedule::{Order, WorkQueue};
+
 const SMALL_PRIMES: [u32; 168] = [
     2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
     101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
@@ -43,52 +53,182 @@ fn generate_primes(limit: u32) -> Vec<u32> {
         .collect();
 }
 
-fn prime_seq(mut n: u32, modulus: &Integer) -> Integer {
-    let mut m = Integer::from(4);
-
-    while n > 1 {
-        m.square_mut();
-        m -= 2;
-        m %= modulus;
-        n -= 1;
+// Small exponents take the fixed-width path of limbs.rs. They are over in
+// well under a second, so it leaves out `safe_point`: nothing to checkpoint.
+// `threads` is asked before every other squaring how many threads it may use.
+fn run_test(
+    test: Test,
+    prime: u32,
+    start: Residue,
+    threads: &dyn Fn() -> usize,
+    safe_point: &mut dyn FnMut(&Residue, u32) -> bool,
+) -> Result<Outcome, Stopped> {
+    if test == Test::LucasLehmer {
+        if let Some(outcome) = limbs::lucas_lehmer(prime, start.iteration, &start.value.to_limbs()) {
+            return Ok(outcome);
+        }
     }
-    m
+    let mut squarer = Plain::new(prime, threads);
+    test.run(prime, start, &mut squarer, &ErrorChecks::default(), safe_point)
 }
 
-fn is_mersenne_prime(prime: u32) -> bool {
-    let mut m = Integer::from(1) << prime;
-    m -= 1;
+// Trial factoring comes first, the LL or PRP test only runs when it finds nothing.
+// Exponents with a factor in the ledger are skipped, and a checkpoint means
+// trial factoring already came up empty. None when the test's checks kept
+// failing, so that it says nothing about M_p.
+fn is_mersenne_prime(
+    prime: u32,
+    test: Test,
+    tf_depth: Option<u32>,
+    threads: &dyn Fn() -> usize,
+    checkpoints: &Checkpoints,
+    ledger: &Ledger,
+) -> Option<bool> {
+    if ledger.factor(prime).is_some() {
+        return Some(false);
+    }
+    let checkpoint = checkpoints.load(prime);
+    if checkpoint.is_none() {
+        let test_start = Instant::now();
+        let depth = tf_depth.unwrap_or_else(|| factor::default_depth(prime));
+        if !ledger.factored_to(prime, depth) {
+            let factor = match factor::trial_factor(prime, depth, &mut || true) {
+                Some(Factoring::Factor(q)) => Some(q.to_string()),
+                _ => None,
+            };
+            let found = factor.is_some();
+            ledger.append(&Record {
+                depth: Some(depth),
+                ..Record::factor(prime, "TF", factor, test_start.elapsed())
+            });
+            if found {
+                return Some(false);
+            }
+        }
+    }
 
-    let s = prime_seq(prime - 1, &m);
-    let is_prime = s == 0;
+    let test_start = Instant::now();
+    let start = checkpoint.unwrap_or_else(|| test.start());
+    let mut saver = checkpoints.saver(prime);
+    let mut safe_point = |residue: &Residue, _| {
+        saver.tick(residue, false);
+        true
+    };
+    let outcome = match run_test(test, prime, start, threads, &mut safe_point) {
+        Ok(outcome) => outcome,
+        // The checkpoint stays, holding the last state that passed a check
+        Err(_) => {
+            eprintln!("M{} skipped, its checks keep failing", prime);
+            return None;
+        }
+    };
+    checkpoints.remove(prime);
 
-    is_prime
+    // Neither test applies to p = 2, so its result is not worth keeping
+    if prime != 2 {
+        ledger.append(&Record::new(prime, outcome, test.name(), test_start.elapsed()));
+    }
+    Some(outcome.is_prime)
 }
 
-const N_THREADS: usize = 6;
-
-fn generate_threads(send: Sender<u32>, primes: Vec<u32>) {
+// Threads take the next exponent when they are free, so none sits idle
+// while others still have a backlog. Once the backlog is gone, the threads
+// left without an exponent help square the residues of the last few, up to
+// --square-threads per test (see WorkQueue::threads_per_test).
+fn generate_threads(
+    threads: usize,
+    send: Sender<(u32, Option<bool>)>,
+    work: Arc<WorkQueue>,
+    test: Test,
+    tf_depth: Option<u32>,
+    checkpoints: Arc<Checkpoints>,
+    ledger: Arc<Ledger>,
+) {
+    let square_threads = initialize_square_threads(threads);
 
-    for i in 0..N_THREADS {
+    for _ in 0..threads {
         let sender = send.clone();
-        let primes = primes.clone();
+        let work = Arc::clone(&work);
+        let checkpoints = Arc::clone(&checkpoints);
+        let ledger = Arc::clone(&ledger);
 
         thread::spawn(move || {
-            let mut k = i;
-            while k < primes.len() {
-                let prime = primes[k];
-                if is_mersenne_prime(prime) {
-                    sender.send(prime).unwrap();
+            let per_test = || work.threads_per_test(threads).min(square_threads);
+            while let Some(prime) = work.next() {
+                // Primes and skipped exponents are reported, composites are not
+                match is_mersenne_prime(prime, test, tf_depth, &per_test, &checkpoints, &ledger) {
+                    Some(false) => {}
+                    result => sender.send((prime, result)).unwrap(),
                 }
-                k += N_THREADS;
+                work.finish();
             }
         });
     }
 }
 
 
+// Unfinished tests are saved every --checkpoint-interval seconds (0 turns this off)
+// and picked up again on the next run
+fn initialize_checkpoints(test: Test) -> Checkpoints {
+    Checkpoints::new(
+        PathBuf::from(get_flag("checkpoint-dir").unwrap_or_else(|| String::from("checkpoints"))),
+        Duration::from_secs(
+            get_flag("checkpoint-interval")
+                .and_then(|secs| secs.parse().ok())
+                .unwrap_or(600),
+        ),
+        test,
+    )
+}
+
+// Every result is appended to the ledger and compared with earlier runs
+fn initialize_ledger() -> Ledger {
+    let path = get_flag("ledger").unwrap_or_else(|| String::from("results.jsonl"));
+    let ledger = Ledger::open(Path::new(&path)).expect("Could not open the ledger");
+    println!("Ledger {} holds {} earlier results", path, ledger.known_results());
+    ledger
+}
+
+// --order=largest-first (the default), smallest-first or interleaved
+fn initialize_order() -> Order {
+    let name = get_flag("order").unwrap_or_else(|| String::from("largest-first"));
+    Order::parse(&name).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    })
+}
+
+// One worker per core unless --threads, MERSENNE_THREADS or the config file say otherwise
+fn initialize_threads() -> usize {
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    let threads = get_setting("threads").and_then(|threads| threads.parse().ok());
+    threads.filter(|&threads| threads > 0).unwrap_or(cores)
+}
+
+// The most threads one squaring is split over once there are more threads
+// than exponents left; --square-threads=1 keeps every test on one thread
+fn initialize_square_threads(threads: usize) -> usize {
+    let square_threads = get_setting("square-threads").and_then(|n| n.parse().ok());
+    square_threads.filter(|&n| n > 0).unwrap_or(threads)
+}
+
+// --test=ll (the default) or --test=prp
+fn initialize_test() -> Test {
+    let name = get_setting("test").unwrap_or_else(|| String::from("ll"));
+    Test::parse(&name).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    })
+}
+
+// Trial factoring looks for factors below 2^depth; by default the depth grows
+// with the exponent (see factor::default_depth), --tf-depth=0 turns it off
+fn initialize_tf_depth() -> Option<u32> {
+    get_setting("tf-depth").and_then(|depth| depth.parse().ok())
+}
+
 fn initialize_primes() -> Vec<u32> {
-    let args: Vec<String> = env::args().collect();
+    let args = args::positional_args();
 
     let arg = args.get(1).expect("Argument required!");
     let upper_bound: u32 = arg.parse().unwrap_or(1000);
@@ -97,20 +237,38 @@ fn initialize_primes() -> Vec<u32> {
 }
 
 fn main() {
-    let primes = initialize_primes();
+    let primes = Arc::new(initialize_primes());
+    let threads = initialize_threads();
+    let order = initialize_order();
+    let test = initialize_test();
+    println!(
+        "Testing {} exponents with {} on {} threads, {}",
+        primes.len(),
+        test.name(),
+        threads,
+        order.name()
+    );
+    let work = Arc::new(WorkQueue::new(primes, order));
+    let checkpoints = Arc::new(initialize_checkpoints(test));
+    let ledger = Arc::new(initialize_ledger());
     let (send, recv) = channel();
 
     let start = Instant::now();
     let mut count = 1;
-    send.send(2).unwrap();
+    send.send((2, Some(true))).unwrap();
 
-    generate_threads(send, primes);
+    generate_threads(threads, send, work, test, initialize_tf_depth(), checkpoints, ledger);
 
     let mut values = vec![];
+    let mut skipped = vec![];
 
     // Real time printing
 
-    for value in recv {
+    for (value, result) in recv {
+        if result.is_none() {
+            skipped.push(value);
+            continue;
+        }
         println!(
             "#{:2} Got value: {:6} after {:.2?}",
             count,
@@ -132,4 +290,8 @@ fn main() {
             val,
         );
     }
+    if !skipped.is_empty() {
+        skipped.sort();
+        println!("Skipped, their checks kept failing: {:?}", skipped);
+    }
 }
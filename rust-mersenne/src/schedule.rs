@@ -0,0 +1,151 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// The order in which the worker threads pick exponents off a job.
+// An LL test costs roughly p^2 log p, so handing out the big ones first keeps
+// every thread busy until the very end; interleaving alternates big and small
+// ones so results keep trickling in while the big ones run.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Order {
+    LargestFirst,
+    SmallestFirst,
+    Interleaved,
+}
+
+impl Order {
+    pub fn parse(name: &str) -> Result<Order, String> {
+        match name {
+            "largest-first" => Ok(Order::LargestFirst),
+            "smallest-first" => Ok(Order::SmallestFirst),
+            "interleaved" => Ok(Order::Interleaved),
+            _ => Err(format!(
+                "unknown order {}, expected largest-first, smallest-first or interleaved",
+                name
+            )),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Order::LargestFirst => "largest-first",
+            Order::SmallestFirst => "smallest-first",
+            Order::Interleaved => "interleaved",
+        }
+    }
+
+    // Position in an ascending list of `len` exponents of the `k`-th one handed out
+    fn index(self, k: usize, len: usize) -> usize {
+        match self {
+            Order::SmallestFirst => k,
+            Order::LargestFirst => len - 1 - k,
+            Order::Interleaved => match k % 2 {
+                0 => len - 1 - k / 2,
+                _ => k / 2,
+            },
+        }
+    }
+}
+
+// Exponents shared by all the worker threads of a job; each one takes the next
+// exponent as soon as it is done with the previous one
+
+pub struct WorkQueue {
+    exponents: Arc<Vec<u32>>,
+    order: Order,
+    taken: AtomicUsize,
+    finished: AtomicUsize,
+}
+
+impl WorkQueue {
+    // `exponents` must be sorted in ascending order
+    pub fn new(exponents: Arc<Vec<u32>>, order: Order) -> WorkQueue {
+        WorkQueue {
+            exponents,
+            order,
+            taken: AtomicUsize::new(0),
+            finished: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn next(&self) -> Option<u32> {
+        let len = self.exponents.len();
+        let k = self.taken.fetch_add(1, Ordering::SeqCst);
+        if k < len {
+            Some(self.exponents[self.order.index(k, len)])
+        } else {
+            None
+        }
+    }
+
+    // Called by a worker once it is done with an exponent it took
+    pub fn finish(&self) {
+        self.finished.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // How many threads each test may split its squarings over, out of `threads`
+    // workers: one while exponents are still waiting, as every thread then has
+    // a test of its own. Once all of them are handed out and fewer than
+    // `threads` are still under test, the idle workers are shared out among those.
+    pub fn threads_per_test(&self, threads: usize) -> usize {
+        let len = self.exponents.len();
+        let left = len.saturating_sub(self.finished.load(Ordering::SeqCst));
+        if self.taken.load(Ordering::SeqCst) < len || left == 0 {
+            1
+        } else {
+            (threads / left).max(1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(order: Order) -> Vec<u32> {
+        let queue = WorkQueue::new(Arc::new(vec![2, 3, 5, 7, 11]), order);
+        std::iter::from_fn(|| queue.next()).collect()
+    }
+
+    #[test]
+    fn orders() {
+        assert_eq!(drain(Order::SmallestFirst), vec![2, 3, 5, 7, 11]);
+        assert_eq!(drain(Order::LargestFirst), vec![11, 7, 5, 3, 2]);
+        assert_eq!(drain(Order::Interleaved), vec![11, 2, 7, 3, 5]);
+        assert_eq!(Order::parse("interleaved"), Ok(Order::Interleaved));
+        assert!(Order::parse("random").is_err());
+    }
+
+    #[test]
+    fn each_exponent_is_handed_out_once() {
+        let queue = Arc::new(WorkQueue::new(Arc::new((0..1000).collect()), Order::LargestFirst));
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                std::thread::spawn(move || std::iter::from_fn(|| queue.next()).collect::<Vec<_>>())
+            })
+            .collect();
+        let mut all: Vec<u32> = workers.into_iter().flat_map(|w| w.join().unwrap()).collect();
+        all.sort();
+        assert_eq!(all, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn idle_threads_go_to_the_last_tests() {
+        let queue = WorkQueue::new(Arc::new(vec![2, 3, 5, 7, 11]), Order::LargestFirst);
+        assert_eq!(queue.next(), Some(11));
+        assert_eq!(queue.threads_per_test(4), 1);
+        for _ in 0..4 {
+            queue.next();
+        }
+        // Five tests running on four threads
+        assert_eq!(queue.threads_per_test(4), 1);
+        queue.finish();
+        queue.finish();
+        assert_eq!(queue.threads_per_test(4), 1);
+        queue.finish();
+        assert_eq!(queue.threads_per_test(4), 2);
+        queue.finish();
+        assert_eq!(queue.threads_per_test(4), 4);
+    }
+}
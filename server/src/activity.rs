@@ -0,0 +1,154 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use common::json;
+
+use crate::costmodel;
+
+// The exponent each worker thread is testing right now and how far along it is.
+// A worker bumps its iteration counter on every LL iteration, which is just an
+// atomic store; readers turn it into a rate and the time left on that exponent.
+
+struct Running {
+    prime: u32,
+    // Where the test started (non-zero when resumed from a checkpoint)
+    first_iteration: u32,
+    iterations: u32,
+    started: Instant,
+    iteration: Arc<AtomicU32>,
+}
+
+// One worker's test as seen at some instant
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub slot: usize,
+    pub prime: u32,
+    pub iteration: u32,
+    // p - 2 squarings in all for LL, p for PRP
+    pub iterations: u32,
+    pub per_sec: f64,
+    // None until the first iteration is done
+    pub eta_secs: Option<f64>,
+}
+
+impl Snapshot {
+    pub fn describe(&self) -> String {
+        format!(
+            "M{} at {}/{} ({:.1} it/s, {} left)",
+            self.prime,
+            self.iteration,
+            self.iterations,
+            self.per_sec,
+            costmodel::format_secs(self.eta_secs)
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"thread\": {}, \"exponent\": {}, \"iteration\": {}, \"iterations\": {}, \
+             \"iterations_per_sec\": {}, \"eta_secs\": {}}}",
+            self.slot + 1,
+            self.prime,
+            self.iteration,
+            self.iterations,
+            json::f64(self.per_sec),
+            json::f64(self.eta_secs.unwrap_or(f64::NAN))
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct Activity {
+    slots: Mutex<Vec<Option<Running>>>,
+}
+
+impl Activity {
+    pub fn new() -> Activity {
+        Activity::default()
+    }
+
+    // Called when `slot` starts on `prime`; the worker stores its iteration in the returned counter
+    pub fn begin(&self, slot: usize, prime: u32, first_iteration: u32, iterations: u32) -> Arc<AtomicU32> {
+        let iteration = Arc::new(AtomicU32::new(first_iteration));
+        let mut slots = self.slots.lock().unwrap();
+        if slots.len() <= slot {
+            slots.resize_with(slot + 1, || None);
+        }
+        slots[slot] = Some(Running {
+            prime,
+            first_iteration,
+            iterations,
+            started: Instant::now(),
+            iteration: Arc::clone(&iteration),
+        });
+        iteration
+    }
+
+    pub fn end(&self, slot: usize) {
+        if let Some(running) = self.slots.lock().unwrap().get_mut(slot) {
+            *running = None;
+        }
+    }
+
+    // The rate is averaged over the whole test so far, so time spent paused lowers it
+    pub fn snapshot(&self) -> Vec<Snapshot> {
+        let slots = self.slots.lock().unwrap();
+        slots
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, running)| {
+                let running = running.as_ref()?;
+                let iteration = running.iteration.load(Ordering::Relaxed);
+                let iterations = running.iterations;
+                let done = iteration.saturating_sub(running.first_iteration);
+                let elapsed = running.started.elapsed().as_secs_f64();
+                let per_sec = if elapsed > 0.0 { done as f64 / elapsed } else { 0.0 };
+                let eta_secs = if done > 0 {
+                    Some(iterations.saturating_sub(iteration) as f64 / per_sec)
+                } else {
+                    None
+                };
+                Some(Snapshot {
+                    slot,
+                    prime: running.prime,
+                    iteration,
+                    iterations,
+                    per_sec,
+                    eta_secs,
+                })
+            })
+            .collect()
+    }
+
+    pub fn current(&self, slot: usize) -> Option<Snapshot> {
+        self.snapshot().into_iter().find(|snapshot| snapshot.slot == slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_each_slot() {
+        let activity = Activity::new();
+        assert!(activity.snapshot().is_empty());
+
+        let counter = activity.begin(2, 86243, 1000, 86241);
+        activity.begin(0, 11, 0, 9);
+        assert_eq!(activity.current(0).unwrap().eta_secs, None);
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        counter.store(1500, Ordering::Relaxed);
+        let snapshot = activity.current(2).unwrap();
+        assert_eq!((snapshot.prime, snapshot.iteration, snapshot.iterations), (86243, 1500, 86241));
+        assert!(snapshot.per_sec > 0.0 && snapshot.eta_secs.unwrap() > 0.0);
+        assert!(snapshot.describe().starts_with("M86243 at 1500/86241 ("));
+
+        activity.end(2);
+        activity.end(7);
+        let slots: Vec<usize> = activity.snapshot().iter().map(|s| s.slot).collect();
+        assert_eq!(slots, vec![0]);
+    }
+}
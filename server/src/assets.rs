@@ -0,0 +1,203 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The dashboard is compiled into the binary, so the server runs from any directory.
+// A `--www-dir` override is read from disk first, to edit the page without rebuilding.
+
+const EMBEDDED: [(&str, &[u8]); 2] = [
+    ("index.html", include_bytes!("www/index.html")),
+    ("main.js", include_bytes!("www/main.js")),
+];
+
+pub struct Asset {
+    pub contents: Vec<u8>,
+    pub content_type: &'static str,
+    pub etag: String,
+    pub last_modified: Option<SystemTime>,
+}
+
+pub fn content_type(name: &str) -> &'static str {
+    let extension = name.rsplit('.').next().unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "ico" => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|d| d as u8)
+}
+
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let high = hex_value(*bytes.get(i + 1)?)?;
+            let low = hex_value(*bytes.get(i + 2)?)?;
+            out.push(high * 16 + low);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+// Turns a request path into a path relative to the asset root, or None if it
+// could point anywhere else. Only plain file and directory names are allowed,
+// which rules out `..`, absolute paths, backslashes and NUL bytes alike.
+pub fn safe_relative_path(request_path: &str) -> Option<String> {
+    let decoded = percent_decode(request_path)?;
+    let relative = decoded.strip_prefix('/')?;
+    let relative = if relative.is_empty() { "index.html" } else { relative };
+
+    let segments: Vec<&str> = relative.split('/').collect();
+    let valid_segment = |segment: &&str| {
+        !segment.is_empty()
+            && !segment.starts_with('.')
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+    };
+    if segments.iter().all(valid_segment) {
+        Some(segments.join("/"))
+    } else {
+        None
+    }
+}
+
+// FNV-1a, good enough to tell two versions of a file apart
+fn etag(contents: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in contents {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("\"{:016x}-{:x}\"", hash, contents.len())
+}
+
+fn from_disk(root: &Path, relative: &str) -> Option<Asset> {
+    let path: PathBuf = root.join(relative);
+    let metadata = fs::metadata(&path).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    let contents = fs::read(&path).ok()?;
+    Some(Asset {
+        etag: etag(&contents),
+        content_type: content_type(relative),
+        last_modified: metadata.modified().ok(),
+        contents,
+    })
+}
+
+fn embedded(relative: &str) -> Option<Asset> {
+    let (_, contents) = EMBEDDED.iter().find(|(name, _)| *name == relative)?;
+    // Embedded files are as old as the binary that carries them
+    let built = env::current_exe()
+        .and_then(fs::metadata)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    Some(Asset {
+        contents: contents.to_vec(),
+        content_type: content_type(relative),
+        etag: etag(contents),
+        last_modified: built,
+    })
+}
+
+pub fn lookup(request_path: &str, override_dir: Option<&Path>) -> Option<Asset> {
+    let relative = safe_relative_path(request_path)?;
+    if let Some(root) = override_dir {
+        if let Some(asset) = from_disk(root, &relative) {
+            return Some(asset);
+        }
+    }
+    embedded(&relative)
+}
+
+// IMF-fixdate as used by Last-Modified, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+pub fn http_date(time: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn safe_paths() {
+        assert_eq!(safe_relative_path("/").as_deref(), Some("index.html"));
+        assert_eq!(safe_relative_path("/main.js").as_deref(), Some("main.js"));
+        assert_eq!(safe_relative_path("/css/site.css").as_deref(), Some("css/site.css"));
+    }
+
+    #[test]
+    fn escaping_paths() {
+        assert_eq!(safe_relative_path("/../Cargo.toml"), None);
+        assert_eq!(safe_relative_path("/%2e%2e/Cargo.toml"), None);
+        assert_eq!(safe_relative_path("/a/../../etc/passwd"), None);
+        assert_eq!(safe_relative_path("//etc/passwd"), None);
+        assert_eq!(safe_relative_path("/..%5c..%5cwindows"), None);
+        assert_eq!(safe_relative_path("/main.js%00.html"), None);
+        assert_eq!(safe_relative_path("/.hidden"), None);
+    }
+
+    #[test]
+    fn embedded_assets() {
+        let index = lookup("/", None).unwrap();
+        assert_eq!(index.content_type, "text/html; charset=utf-8");
+        let script = lookup("/main.js", None).unwrap();
+        assert_eq!(script.content_type, "text/javascript; charset=utf-8");
+        assert!(lookup("/missing.css", None).is_none());
+    }
+
+    #[test]
+    fn dates() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(http_date(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+    }
+}
@@ -1,7 +1,15 @@
-use std::sync::mpsc::Receiver;
-use std::time::Instant;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
 
-pub fn console_reporter(recv: Receiver<u32>) {
+// How often a progress line is printed when no prime turns up
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(60);
+
+// `progress` is the overall progress, `running` a line per exponent under test
+pub fn console_reporter(
+    recv: Receiver<u32>,
+    progress: impl Fn() -> String,
+    running: impl Fn() -> Vec<String>,
+) {
     let start = Instant::now();
     let mut count = 1;
 
@@ -9,12 +17,24 @@ pub fn console_reporter(recv: Receiver<u32>) {
 
     // Real time printing
 
-    for value in recv {
+    loop {
+        let value = match recv.recv_timeout(PROGRESS_INTERVAL) {
+            Ok(value) => value,
+            Err(RecvTimeoutError::Timeout) => {
+                println!("[{:.0?}] {}", start.elapsed(), progress());
+                for line in running() {
+                    println!("    {}", line);
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
         println!(
-            "#{:2} Got value: {:6} after {:.2?}",
+            "#{:2} Got value: {:6} after {:.2?} ({})",
             count,
             value,
-            start.elapsed()
+            start.elapsed(),
+            progress()
         );
         count += 1;
         values.push(value);
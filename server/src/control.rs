@@ -0,0 +1,158 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+// More workers than this is surely a typo
+pub const MAX_THREADS: usize = 1024;
+
+// How often a paused worker checks whether its job was cancelled meanwhile
+const CANCEL_POLL: Duration = Duration::from_millis(200);
+
+// Commands accepted from interactive clients (see websocket.rs)
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Pause,
+    Resume,
+    AddRange(u32, u32),
+    Cancel(u32),
+    Threads(usize),
+    Stats,
+}
+
+impl Command {
+    pub fn parse(text: &str) -> Result<Command, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words.as_slice() {
+            ["pause"] => Ok(Command::Pause),
+            ["resume"] => Ok(Command::Resume),
+            ["stats"] => Ok(Command::Stats),
+            ["add", lower, upper] => {
+                let lower = parse_bound(lower)?;
+                let upper = parse_bound(upper)?;
+                if lower > upper {
+                    return Err(format!("empty range [{},{}]", lower, upper));
+                }
+                Ok(Command::AddRange(lower, upper))
+            }
+            ["threads", count] => {
+                let count = parse_bound(count)? as usize;
+                if count == 0 || count > MAX_THREADS {
+                    return Err(format!("thread count must be between 1 and {}", MAX_THREADS));
+                }
+                Ok(Command::Threads(count))
+            }
+            ["cancel", id] => {
+                let id = id.parse().map_err(|_| format!("not a job id: {}", id))?;
+                Ok(Command::Cancel(id))
+            }
+            _ => Err(format!("unknown command: {}", text.trim())),
+        }
+    }
+}
+
+fn parse_bound(word: &str) -> Result<u32, String> {
+    word.replace('_', "")
+        .parse()
+        .map_err(|_| format!("not a number: {}", word))
+}
+
+// Run-wide switches shared by the workers and the server.
+// Workers pass through `safe_point` between Lucas-Lehmer iterations, which is
+// where a pause takes effect. The flag is atomic so the common case costs one load.
+// The worker count is only looked at between exponents.
+
+pub struct Control {
+    paused: AtomicBool,
+    lock: Mutex<()>,
+    resumed: Condvar,
+    threads: AtomicUsize,
+}
+
+impl Control {
+    pub fn new(threads: usize) -> Control {
+        Control {
+            paused: AtomicBool::new(false),
+            lock: Mutex::new(()),
+            resumed: Condvar::new(),
+            threads: AtomicUsize::new(threads.clamp(1, MAX_THREADS)),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads.load(Ordering::SeqCst)
+    }
+
+    // Clamped to 1..=MAX_THREADS; returns the count actually set
+    pub fn set_threads(&self, threads: usize) -> usize {
+        let threads = threads.clamp(1, MAX_THREADS);
+        self.threads.store(threads, Ordering::SeqCst);
+        threads
+    }
+
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        let _guard = self.lock.lock().unwrap();
+        self.paused.store(false, Ordering::SeqCst);
+        self.resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    // Blocks while paused. Returns false if the job was cancelled, true to carry on.
+    pub fn safe_point(&self, cancelled: &AtomicBool) -> bool {
+        if self.is_paused() {
+            let mut guard = self.lock.lock().unwrap();
+            while self.is_paused() && !cancelled.load(Ordering::SeqCst) {
+                guard = self.resumed.wait_timeout(guard, CANCEL_POLL).unwrap().0;
+            }
+        }
+        !cancelled.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Command::parse("pause"), Ok(Command::Pause));
+        assert_eq!(Command::parse(" resume \n"), Ok(Command::Resume));
+        assert_eq!(
+            Command::parse("add 10_000 20000"),
+            Ok(Command::AddRange(10_000, 20_000))
+        );
+        assert!(Command::parse("add 20 10").is_err());
+        assert!(Command::parse("add ten 20").is_err());
+        assert_eq!(Command::parse("cancel 3"), Ok(Command::Cancel(3)));
+        assert!(Command::parse("cancel all").is_err());
+        assert_eq!(Command::parse("threads 8"), Ok(Command::Threads(8)));
+        assert!(Command::parse("threads 0").is_err());
+        assert!(Command::parse("stop").is_err());
+    }
+
+    #[test]
+    fn cancel_releases_a_paused_worker() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let control = Arc::new(Control::new(1));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        assert!(control.safe_point(&cancelled));
+
+        control.pause();
+        let worker = {
+            let control = Arc::clone(&control);
+            let cancelled = Arc::clone(&cancelled);
+            thread::spawn(move || control.safe_point(&cancelled))
+        };
+        cancelled.store(true, Ordering::SeqCst);
+        assert!(!worker.join().unwrap());
+    }
+}
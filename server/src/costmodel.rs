@@ -0,0 +1,264 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::jobs::JobQueue;
+
+// Estimated wall time of one Lucas-Lehmer test: seconds = a * p^b.
+// p - 2 squarings of p-bit numbers put b somewhere between 2 and 3 depending on
+// the multiplication algorithm, so a and b are fitted by least squares on
+// ln(seconds) = ln(a) + b ln(p) over the tests timed so far.
+// The running sums of the fit are saved, so the next run starts calibrated.
+
+// Used until two different exponents have been timed
+const DEFAULT_SLOPE: f64 = 2.5;
+const SLOPE_RANGE: (f64, f64) = (1.0, 4.0);
+
+// Shorter tests measure mostly overhead, they would skew the fit
+const MIN_SAMPLE: Duration = Duration::from_millis(1);
+
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Fit {
+    n: f64,
+    sum_x: f64,
+    sum_y: f64,
+    sum_xx: f64,
+    sum_xy: f64,
+}
+
+impl Fit {
+    fn add(&mut self, x: f64, y: f64) {
+        self.n += 1.0;
+        self.sum_x += x;
+        self.sum_y += y;
+        self.sum_xx += x * x;
+        self.sum_xy += x * y;
+    }
+
+    // (ln a, b), or None before the first sample
+    fn coefficients(&self) -> Option<(f64, f64)> {
+        if self.n < 1.0 {
+            return None;
+        }
+        let spread = self.n * self.sum_xx - self.sum_x * self.sum_x;
+        let slope = if self.n >= 2.0 && spread > 1e-9 * self.n * self.n {
+            (self.n * self.sum_xy - self.sum_x * self.sum_y) / spread
+        } else {
+            DEFAULT_SLOPE
+        };
+        let slope = slope.clamp(SLOPE_RANGE.0, SLOPE_RANGE.1);
+        Some((self.intercept(slope), slope))
+    }
+
+    // The best ln a for a given b
+    fn intercept(&self, slope: f64) -> f64 {
+        (self.sum_y - slope * self.sum_x) / self.n
+    }
+
+    fn to_text(self) -> String {
+        let mut out = String::from("# Cost model: seconds = a * p^b, fitted on ln(seconds) = ln(a) + b ln(p)\n");
+        if let Some((intercept, slope)) = self.coefficients() {
+            out.push_str(&format!("# a = {:e}, b = {:.4}\n", intercept.exp(), slope));
+        }
+        out.push_str(&format!(
+            "samples = {}\nsum_x = {}\nsum_y = {}\nsum_xx = {}\nsum_xy = {}\n",
+            self.n, self.sum_x, self.sum_y, self.sum_xx, self.sum_xy
+        ));
+        out
+    }
+
+    fn from_text(text: &str) -> Option<Fit> {
+        let value = |name: &str| -> Option<f64> {
+            text.lines()
+                .filter(|line| !line.starts_with('#'))
+                .filter_map(|line| line.split_once('='))
+                .find(|(key, _)| key.trim() == name)
+                .and_then(|(_, value)| value.trim().parse().ok())
+                .filter(|value: &f64| value.is_finite())
+        };
+        Some(Fit {
+            n: value("samples")?,
+            sum_x: value("sum_x")?,
+            sum_y: value("sum_y")?,
+            sum_xx: value("sum_xx")?,
+            sum_xy: value("sum_xy")?,
+        })
+    }
+}
+
+pub struct CostModel {
+    path: Option<PathBuf>,
+    state: Mutex<(Fit, Instant)>,
+}
+
+impl CostModel {
+    // Starts from the constants saved at `path` if there are any
+    pub fn load(path: Option<PathBuf>) -> CostModel {
+        let fit = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|text| Fit::from_text(&text))
+            .unwrap_or_default();
+        CostModel {
+            path,
+            state: Mutex::new((fit, Instant::now())),
+        }
+    }
+
+    pub fn observe(&self, prime: u32, took: Duration) {
+        if took < MIN_SAMPLE || prime < 3 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.0.add((prime as f64).ln(), took.as_secs_f64().ln());
+        if state.1.elapsed() >= SAVE_INTERVAL {
+            state.1 = Instant::now();
+            let fit = state.0;
+            drop(state);
+            self.report_save(fit);
+        }
+    }
+
+    pub fn save(&self) {
+        let fit = self.state.lock().unwrap().0;
+        self.report_save(fit);
+    }
+
+    fn report_save(&self, fit: Fit) {
+        if let Err(error) = self.write(fit) {
+            eprintln!("Could not save the cost model: {}", error);
+        }
+    }
+
+    // Replaced atomically like the checkpoints, a crash never leaves half a file
+    fn write(&self, fit: Fit) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let temp = path.with_extension("tmp");
+        let mut file = File::create(&temp)?;
+        file.write_all(fit.to_text().as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    }
+
+    // (a, b), or None while nothing has been timed yet
+    pub fn coefficients(&self) -> Option<(f64, f64)> {
+        let fit = self.state.lock().unwrap().0;
+        fit.coefficients().map(|(intercept, slope)| (intercept.exp(), slope))
+    }
+
+    // The current b, for weighing exponents before any have been timed
+    pub fn slope(&self) -> f64 {
+        self.coefficients().map_or(DEFAULT_SLOPE, |(_, slope)| slope)
+    }
+
+    // Progress over all jobs weighted by the estimated cost of each exponent.
+    // The queue weighs exponents with the b it was created with, so the time
+    // left uses the a that fits best with that b, assuming all `threads` stay busy.
+    pub fn progress(&self, jobs: &JobQueue, threads: usize) -> Progress {
+        let fit = self.state.lock().unwrap().0;
+        let (done, left) = jobs.work();
+        let total = done + left;
+        Progress {
+            fraction: if total > 0.0 { Some(done / total) } else { None },
+            eta_secs: (fit.n >= 1.0)
+                .then(|| fit.intercept(jobs.slope()).exp() * left / threads.max(1) as f64),
+        }
+    }
+}
+
+pub struct Progress {
+    pub fraction: Option<f64>,
+    pub eta_secs: Option<f64>,
+}
+
+impl Progress {
+    pub fn describe(&self) -> String {
+        match self.fraction {
+            Some(fraction) => format!(
+                "~{:.2}% of the work done, {} left",
+                100.0 * fraction,
+                format_secs(self.eta_secs)
+            ),
+            None => String::from("no work queued"),
+        }
+    }
+}
+
+// Seconds as "1d 02:03:04", or "-" when there is no estimate
+pub fn format_secs(secs: Option<f64>) -> String {
+    match secs {
+        Some(secs) if secs.is_finite() && secs >= 0.0 => {
+            let secs = secs.round() as u64;
+            format!(
+                "{}d {:02}:{:02}:{:02}",
+                secs / 86400,
+                secs / 3600 % 24,
+                secs / 60 % 60,
+                secs % 60
+            )
+        }
+        _ => String::from("-"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_a_power_law() {
+        let model = CostModel::load(None);
+        assert_eq!(model.coefficients(), None);
+
+        // seconds = 1e-9 * p^2.5
+        for &p in &[3001u32, 5003, 10007, 20011] {
+            let secs = 1e-9 * (p as f64).powf(2.5);
+            model.observe(p, Duration::from_secs_f64(secs));
+        }
+        let (a, b) = model.coefficients().unwrap();
+        assert!((b - 2.5).abs() < 1e-3, "b = {}", b);
+        assert!((a / 1e-9 - 1.0).abs() < 1e-2, "a = {}", a);
+    }
+
+    #[test]
+    fn progress_of_a_job() {
+        use crate::jobs::JobSpec;
+
+        let model = CostModel::load(None);
+        // seconds = 1e-6 * p^2
+        for &p in &[1009u32, 2003, 4001] {
+            model.observe(p, Duration::from_secs_f64(1e-6 * (p as f64).powi(2)));
+        }
+        let jobs = JobQueue::new(2.0);
+        let id = jobs.submit(JobSpec::Exponents(vec![1009, 2003]));
+        jobs.next_job();
+        jobs.record(id, 2003, Some(false));
+        let progress = model.progress(&jobs, 1);
+        let fraction = 2003.0f64.powi(2) / (1009.0f64.powi(2) + 2003.0f64.powi(2));
+        assert!((progress.fraction.unwrap() - fraction).abs() < 1e-9);
+        assert!((progress.eta_secs.unwrap() - 1e-6 * 1009.0f64.powi(2)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn constants_survive_a_restart() {
+        let mut fit = Fit::default();
+        fit.add(8.0, -3.0);
+        fit.add(9.0, -0.5);
+        assert_eq!(Fit::from_text(&fit.to_text()), Some(fit));
+        assert_eq!(Fit::from_text("samples = 1\n"), None);
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(format_secs(Some(93784.4)), "1d 02:03:04");
+        assert_eq!(format_secs(None), "-");
+        assert_eq!(format_secs(Some(f64::NAN)), "-");
+    }
+}
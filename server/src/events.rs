@@ -0,0 +1,105 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+
+// A single Server-Sent Event, already formatted as `event:`/`data:` lines
+
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub name: &'static str,
+    pub data: String,
+}
+
+impl Event {
+    pub fn found(exponent: u32) -> Event {
+        Event {
+            name: "found",
+            data: format!("{{\"exponent\": {}}}", exponent),
+        }
+    }
+
+    pub fn checked(exponent: u32, thread: usize, is_prime: bool, millis: u128) -> Event {
+        Event {
+            name: "checked",
+            data: format!(
+                "{{\"exponent\": {}, \"thread\": {}, \"is_prime\": {}, \"millis\": {}}}",
+                exponent,
+                thread + 1,
+                is_prime,
+                millis
+            ),
+        }
+    }
+
+    pub fn to_sse(&self) -> String {
+        format!("event: {}\ndata: {}\n\n", self.name, self.data)
+    }
+}
+
+// Fan-out of events to every connected /events client.
+// Each subscriber gets its own channel; dead ones are dropped on the next publish.
+
+pub struct EventHub {
+    subscribers: Mutex<Vec<Sender<Event>>>,
+}
+
+impl EventHub {
+    pub fn new() -> EventHub {
+        EventHub {
+            subscribers: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (send, recv) = channel();
+        self.subscribers.lock().unwrap().push(send);
+        recv
+    }
+
+    pub fn publish(&self, event: Event) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|sub| sub.send(event.clone()).is_ok());
+    }
+
+    #[cfg(test)]
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_subscriber_gets_the_event() {
+        let hub = EventHub::new();
+        let first = hub.subscribe();
+        let second = hub.subscribe();
+
+        hub.publish(Event::found(127));
+
+        assert_eq!(first.recv().unwrap().data, "{\"exponent\": 127}");
+        assert_eq!(second.recv().unwrap().data, "{\"exponent\": 127}");
+    }
+
+    #[test]
+    fn dropped_subscribers_are_forgotten() {
+        let hub = EventHub::new();
+        let kept = hub.subscribe();
+        drop(hub.subscribe());
+
+        hub.publish(Event::found(31));
+
+        assert_eq!(hub.subscriber_count(), 1);
+        assert_eq!(kept.recv().unwrap().name, "found");
+    }
+
+    #[test]
+    fn sse_format() {
+        let event = Event::checked(61, 0, true, 5);
+        assert_eq!(
+            event.to_sse(),
+            "event: checked\ndata: {\"exponent\": 61, \"thread\": 1, \"is_prime\": true, \"millis\": 5}\n\n"
+        );
+    }
+}
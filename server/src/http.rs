@@ -0,0 +1,331 @@
+use std::io::{self, BufRead, Read, Write};
+
+// Limits on what we accept from a client
+
+const MAX_REQUEST_LINE: u64 = 8 * 1024;
+const MAX_HEADER_LINE: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+const MAX_BODY: usize = 64 * 1024;
+
+//============================================================================
+// REQUESTS
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    // Header names are case-insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // HTTP/1.1 keeps the connection open unless told otherwise, HTTP/1.0 the opposite
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("Connection").unwrap_or("").to_ascii_lowercase();
+        let tokens: Vec<&str> = connection.split(',').map(|t| t.trim()).collect();
+        if self.version == "HTTP/1.0" {
+            tokens.contains(&"keep-alive")
+        } else {
+            !tokens.contains(&"close")
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RequestError {
+    // The client closed the connection before sending anything
+    Closed,
+    Io,
+    BadRequest(&'static str),
+    UriTooLong,
+    HeadersTooLarge,
+    PayloadTooLarge,
+    VersionNotSupported,
+}
+
+impl RequestError {
+    // What to answer with, if the connection is still usable for an answer
+    pub fn response(&self) -> Option<Response> {
+        let response = match self {
+            RequestError::Closed | RequestError::Io => return None,
+            RequestError::BadRequest(why) => Response::text(400, why),
+            RequestError::UriTooLong => Response::text(414, "Request line too long"),
+            RequestError::HeadersTooLarge => Response::text(431, "Request headers too large"),
+            RequestError::PayloadTooLarge => Response::text(413, "Request body too large"),
+            RequestError::VersionNotSupported => Response::text(505, "Only HTTP/1.x is supported"),
+        };
+        Some(response)
+    }
+}
+
+impl From<io::Error> for RequestError {
+    fn from(_err: io::Error) -> RequestError {
+        RequestError::Io
+    }
+}
+
+// Reads one CRLF (or bare LF) terminated line of at most `limit` bytes.
+// Returns None at a clean end of stream.
+fn read_line(reader: &mut impl BufRead, limit: u64) -> Result<Option<String>, RequestError> {
+    let mut line = vec![];
+    let read = reader.by_ref().take(limit).read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        if read as u64 == limit {
+            return Err(RequestError::HeadersTooLarge);
+        }
+        return Err(RequestError::BadRequest("Truncated request"));
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| RequestError::BadRequest("Request is not valid UTF-8"))
+}
+
+pub fn read_request(reader: &mut impl BufRead) -> Result<Request, RequestError> {
+    // Tolerate empty lines between pipelined requests (RFC 7230 section 3.5)
+    let request_line = loop {
+        match read_line(reader, MAX_REQUEST_LINE) {
+            Ok(Some(line)) if line.is_empty() => continue,
+            Ok(Some(line)) => break line,
+            Ok(None) => return Err(RequestError::Closed),
+            Err(RequestError::HeadersTooLarge) => return Err(RequestError::UriTooLong),
+            Err(err) => return Err(err),
+        }
+    };
+
+    let parts: Vec<&str> = request_line.split(' ').collect();
+    let (method, target, version) = match parts.as_slice() {
+        [method, target, version] => (*method, *target, *version),
+        _ => return Err(RequestError::BadRequest("Malformed request line")),
+    };
+
+    if method.is_empty() || !method.bytes().all(|b| b.is_ascii_uppercase()) {
+        return Err(RequestError::BadRequest("Malformed method"));
+    }
+    if !target.starts_with('/') {
+        return Err(RequestError::BadRequest("Only origin-form targets are supported"));
+    }
+    if !version.starts_with("HTTP/") {
+        return Err(RequestError::BadRequest("Malformed version"));
+    }
+    if version != "HTTP/1.1" && version != "HTTP/1.0" {
+        return Err(RequestError::VersionNotSupported);
+    }
+
+    let mut headers = vec![];
+    loop {
+        let line = match read_line(reader, MAX_HEADER_LINE)? {
+            Some(line) => line,
+            None => return Err(RequestError::BadRequest("Truncated headers")),
+        };
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(RequestError::HeadersTooLarge);
+        }
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) if !name.is_empty() && !name.ends_with(' ') => (name, value),
+            _ => return Err(RequestError::BadRequest("Malformed header")),
+        };
+        headers.push((name.to_owned(), value.trim().to_owned()));
+    }
+
+    // None of our resources take a query string
+    let path = match target.split_once('?') {
+        Some((path, _query)) => path,
+        None => target,
+    };
+
+    let mut request = Request {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        version: version.to_owned(),
+        headers,
+        body: vec![],
+    };
+
+    if request.header("Transfer-Encoding").is_some() {
+        return Err(RequestError::BadRequest("Chunked request bodies are not supported"));
+    }
+
+    if let Some(length) = request.header("Content-Length") {
+        let length: usize = length
+            .parse()
+            .map_err(|_| RequestError::BadRequest("Invalid Content-Length"))?;
+        if length > MAX_BODY {
+            return Err(RequestError::PayloadTooLarge);
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        request.body = body;
+    }
+
+    Ok(request)
+}
+
+//============================================================================
+// RESPONSES
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> Response {
+        Response {
+            status,
+            headers: vec![(String::from("Content-Type"), content_type.to_owned())],
+            body,
+        }
+    }
+
+    pub fn text(status: u16, body: &str) -> Response {
+        let mut body = body.to_owned();
+        if !body.ends_with('\n') {
+            body.push('\n');
+        }
+        Response::new(status, "text/plain; charset=utf-8", body.into_bytes())
+    }
+
+    pub fn json(status: u16, body: String) -> Response {
+        Response::new(status, "application/json", body.into_bytes())
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    // Content-Length and Connection are always filled in here, never by callers.
+    // For HEAD requests only the head is sent, with the length the body would have had.
+    pub fn write_to(&self, out: &mut impl Write, head_only: bool, keep_alive: bool) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason_phrase(self.status));
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        // A 304 describes the body we did not send, so it must not claim a length of 0
+        if self.status != 304 {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        let connection = if keep_alive { "keep-alive" } else { "close" };
+        head.push_str(&format!("Connection: {}\r\n\r\n", connection));
+
+        let mut bytes = head.into_bytes();
+        if !head_only {
+            bytes.extend_from_slice(&self.body);
+        }
+        out.write_all(&bytes)?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Request, RequestError> {
+        read_request(&mut raw.as_bytes())
+    }
+
+    #[test]
+    fn simple_get() {
+        let request = parse("GET /json?pretty=1 HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/json");
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert!(request.keep_alive());
+    }
+
+    #[test]
+    fn connection_header() {
+        let close = parse("GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        assert!(!close.keep_alive());
+
+        let old = parse("GET / HTTP/1.0\r\n\r\n").unwrap();
+        assert!(!old.keep_alive());
+
+        let old_kept = parse("GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").unwrap();
+        assert!(old_kept.keep_alive());
+    }
+
+    #[test]
+    fn body_is_read_by_length() {
+        let raw = "POST /jobs HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n";
+        let mut reader = raw.as_bytes();
+        let first = read_request(&mut reader).unwrap();
+        assert_eq!(first.body, b"hello");
+        let second = read_request(&mut reader).unwrap();
+        assert_eq!(second.path, "/");
+    }
+
+    #[test]
+    fn malformed_requests() {
+        assert!(matches!(parse(""), Err(RequestError::Closed)));
+        assert!(matches!(parse("GET\r\n\r\n"), Err(RequestError::BadRequest(_))));
+        assert!(matches!(parse("GET / HTTP/1.1\r\nbad\r\n\r\n"), Err(RequestError::BadRequest(_))));
+        assert!(matches!(parse("GET / HTTP/2.0\r\n\r\n"), Err(RequestError::VersionNotSupported)));
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nContent-Length: 999999999\r\n\r\n"),
+            Err(RequestError::PayloadTooLarge)
+        ));
+    }
+
+    #[test]
+    fn long_uri() {
+        let raw = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_REQUEST_LINE as usize));
+        assert!(matches!(parse(&raw), Err(RequestError::UriTooLong)));
+    }
+
+    #[test]
+    fn response_has_length() {
+        let mut out = vec![];
+        Response::text(404, "Not Found").write_to(&mut out, false, false).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(text.contains("Content-Length: 10\r\n"));
+        assert!(text.contains("Connection: close\r\n"));
+        assert!(text.ends_with("\r\n\r\nNot Found\n"));
+    }
+}
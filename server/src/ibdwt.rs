@@ -0,0 +1,410 @@
+use std::f64::consts::PI;
+use std::ops::{Add, Mul, Sub};
+
+use common::bignum::Bignum;
+
+use crate::primes::{Squarer, Stopped};
+
+// Squaring mod M_p = 2^p - 1 with floating point FFTs, by the irrational-base
+// discrete weighted transform of Crandall and Fagin. x is cut into N words,
+// word j holding bits ceil(p j / N) up to ceil(p (j + 1) / N), so a word has
+// either floor(p / N) or ceil(p / N) bits. Weighting word j by
+// 2^(ceil(p j / N) - p j / N) turns the cyclic convolution of the words, which
+// an FFT does in O(N log N), into x^2 mod M_p: where the convolution wraps
+// around is exactly where 2^p = 1, so there is no zero padding and no reduction
+// afterwards, only carries. Words are kept balanced, in [-2^(b-1), 2^(b-1)),
+// which keeps the products and the round-off small.
+
+// Convolution outputs are exact integers; the largest distance from one that
+// is put down to floating point error rather than to a transform too short
+const MAX_ROUNDOFF: f64 = 0.4;
+
+// Past 2^53 a double has no fraction left to show the round-off in
+const EXACT: f64 = (1u64 << 53) as f64;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn conj(self) -> Complex {
+        Complex { re: self.re, im: -self.im }
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, other: Complex) -> Complex {
+        Complex { re: self.re + other.re, im: self.im + other.im }
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, other: Complex) -> Complex {
+        Complex { re: self.re - other.re, im: self.im - other.im }
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex {
+        Complex {
+            re: self.re * other.re - self.im * other.im,
+            im: self.re * other.im + self.im * other.re,
+        }
+    }
+}
+
+// The word sizes, weights and twiddle factors for one transform length
+struct Plan {
+    len: usize,
+    bits: Vec<u32>,
+    starts: Vec<u32>,
+    weights: Vec<f64>,
+    // e^(-2 pi i k / N) for k < N / 2, each computed directly as a recurrence
+    // would pile up the error
+    twiddles: Vec<Complex>,
+}
+
+impl Plan {
+    fn new(prime: u32, len: usize) -> Plan {
+        let (p, n) = (prime as u64, len as u64);
+        let start = |j: u64| (p * j).div_ceil(n);
+        let starts: Vec<u32> = (0..n).map(|j| start(j) as u32).collect();
+        let bits = (0..n).map(|j| (start(j + 1) - start(j)) as u32).collect();
+        let weights = (0..n)
+            .map(|j| ((start(j) * n - p * j) as f64 / n as f64).exp2())
+            .collect();
+        let twiddles = (0..len / 2)
+            .map(|k| {
+                let angle = -2.0 * PI * k as f64 / len as f64;
+                Complex { re: angle.cos(), im: angle.sin() }
+            })
+            .collect();
+        Plan { len, bits, starts, weights, twiddles }
+    }
+
+    // In place radix-2 FFT; the inverse includes the division by N
+    fn fft(&self, data: &mut [Complex], inverse: bool) {
+        let n = data.len();
+        let mut j = 0;
+        for i in 1..n {
+            let mut bit = n >> 1;
+            while j & bit != 0 {
+                j ^= bit;
+                bit >>= 1;
+            }
+            j |= bit;
+            if i < j {
+                data.swap(i, j);
+            }
+        }
+
+        let mut size = 2;
+        while size <= n {
+            let half = size / 2;
+            let step = n / size;
+            for chunk in data.chunks_mut(size) {
+                for k in 0..half {
+                    let w = self.twiddles[k * step];
+                    let w = if inverse { w.conj() } else { w };
+                    let a = chunk[k];
+                    let b = chunk[k + half] * w;
+                    chunk[k] = a + b;
+                    chunk[k + half] = a - b;
+                }
+            }
+            size *= 2;
+        }
+
+        if inverse {
+            let scale = 1.0 / n as f64;
+            for c in data.iter_mut() {
+                c.re *= scale;
+                c.im *= scale;
+            }
+        }
+    }
+}
+
+// The bits per word a length of N can take: products of two b bit words summed
+// N times, with the error of log N butterflies, have to stay well inside the 53
+// bits of a double. Erring too far the other way only shows up as round-off,
+// which moves the test to a longer transform.
+fn max_bits(len: usize) -> f64 {
+    (50.0 - (len as f64).log2()) / 2.0
+}
+
+fn initial_len(prime: u32) -> usize {
+    let mut len = 1;
+    while prime as f64 / len as f64 > max_bits(len) {
+        len *= 2;
+    }
+    len
+}
+
+// v as a word of `bits` bits in [-2^(bits-1), 2^(bits-1)) and what carries out of it
+fn balance(v: i64, bits: u32) -> (i64, i64) {
+    let base = 1i64 << bits;
+    let mut word = v.rem_euclid(base);
+    if word >= base / 2 {
+        word -= base;
+    }
+    (word, (v - word) >> bits)
+}
+
+pub struct Ibdwt {
+    prime: u32,
+    plan: Plan,
+    words: Vec<i64>,
+    // Kept between squarings so they allocate nothing
+    transform: Vec<Complex>,
+    products: Vec<i64>,
+    // The largest round-off of a squaring that was kept
+    max_roundoff: f64,
+}
+
+impl Ibdwt {
+    pub fn new(prime: u32) -> Ibdwt {
+        Ibdwt::with_len(prime, initial_len(prime))
+    }
+
+    fn with_len(prime: u32, len: usize) -> Ibdwt {
+        Ibdwt {
+            prime,
+            plan: Plan::new(prime, len),
+            words: vec![0; len],
+            transform: vec![Complex::default(); len],
+            products: vec![0; len],
+            max_roundoff: 0.0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.plan.len
+    }
+
+    // Sets the words to `products` carried into balanced form. 2^p = 1, so what
+    // carries out of the top word comes back in at the bottom; it gets smaller
+    // every time round.
+    fn carry(&mut self) {
+        let plan = &self.plan;
+        let mut carry = 0;
+        for j in 0..plan.len {
+            let (word, out) = balance(self.products[j] + carry, plan.bits[j]);
+            self.words[j] = word;
+            carry = out;
+        }
+        let mut j = 0;
+        while carry != 0 {
+            let (word, out) = balance(self.words[j] + carry, plan.bits[j]);
+            self.words[j] = word;
+            carry = out;
+            j = (j + 1) % plan.len;
+        }
+    }
+
+    // Squares the words into `products`, unless the round-off says the
+    // transform is too short, in which case nothing changes
+    fn try_square(&mut self) -> Result<(), f64> {
+        let plan = &self.plan;
+        for j in 0..plan.len {
+            self.transform[j] = Complex { re: self.words[j] as f64 * plan.weights[j], im: 0.0 };
+        }
+        plan.fft(&mut self.transform, false);
+        for c in self.transform.iter_mut() {
+            *c = *c * *c;
+        }
+        plan.fft(&mut self.transform, true);
+
+        let mut roundoff: f64 = 0.0;
+        for j in 0..plan.len {
+            let v = self.transform[j].re / plan.weights[j];
+            let rounded = v.round();
+            roundoff = match v.abs() < EXACT {
+                true => roundoff.max((v - rounded).abs()),
+                false => 0.5,
+            };
+            self.products[j] = rounded as i64;
+        }
+        if roundoff > MAX_ROUNDOFF {
+            return Err(roundoff);
+        }
+        self.max_roundoff = self.max_roundoff.max(roundoff);
+        Ok(())
+    }
+
+    // x as 64-bit limbs, least significant first
+    fn limbs(&self) -> Vec<u64> {
+        // Non-negative words this time, which are then just bit fields of x
+        let plan = &self.plan;
+        let mut words = self.words.clone();
+        let mut carry = 0;
+        let mut j = 0;
+        loop {
+            let base = 1i64 << plan.bits[j];
+            let v = words[j] + carry;
+            words[j] = v.rem_euclid(base);
+            carry = (v - words[j]) >> plan.bits[j];
+            j = (j + 1) % plan.len;
+            if j == 0 && carry == 0 {
+                break;
+            }
+        }
+        // All p bits set is M_p, which is 0
+        if words.iter().zip(&plan.bits).all(|(&word, &bits)| word == (1 << bits) - 1) {
+            return vec![];
+        }
+
+        let mut limbs = vec![0u64; (self.prime as usize).div_ceil(64)];
+        for (j, &word) in words.iter().enumerate() {
+            let (limb, offset) = ((plan.starts[j] / 64) as usize, plan.starts[j] % 64);
+            limbs[limb] |= (word as u64) << offset;
+            if offset + plan.bits[j] > 64 {
+                limbs[limb + 1] |= (word as u64) >> (64 - offset);
+            }
+        }
+        limbs
+    }
+
+    // x from limbs as above, for x in [0, M_p]
+    fn set_limbs(&mut self, limbs: &[u64]) {
+        let limb = |i: usize| limbs.get(i).copied().unwrap_or(0);
+        let plan = &self.plan;
+        for j in 0..plan.len {
+            let (i, offset) = ((plan.starts[j] / 64) as usize, plan.starts[j] % 64);
+            let mut field = limb(i) >> offset;
+            if offset > 0 {
+                field |= limb(i + 1) << (64 - offset);
+            }
+            self.products[j] = (field & ((1u64 << plan.bits[j]) - 1)) as i64;
+        }
+        self.carry();
+    }
+
+    // Too much round-off means the words were too wide for the transform to get
+    // every product exactly; the squaring is done again with twice the words.
+    // Once they cannot get any narrower the floating point itself is not to be
+    // trusted, and the squaring gives up.
+    pub fn square(&mut self) -> Result<(), Stopped> {
+        while let Err(roundoff) = self.try_square() {
+            let len = 2 * self.len();
+            if len > self.prime as usize {
+                eprintln!("M{}: round-off {:.3} at every FFT length", self.prime, roundoff);
+                return Err(Stopped::Unreliable);
+            }
+            eprintln!(
+                "Round-off {:.3} squaring mod M{}, moving to an FFT of length {}",
+                roundoff, self.prime, len
+            );
+            let x = self.limbs();
+            let max_roundoff = self.max_roundoff;
+            *self = Ibdwt::with_len(self.prime, len);
+            self.max_roundoff = max_roundoff;
+            self.set_limbs(&x);
+        }
+        self.carry();
+        Ok(())
+    }
+
+    // x - 2^k for k <= p; the word may end up a bit out of balance, which the
+    // next squaring's carries put right
+    pub fn sub_power_of_two(&mut self, k: u32) {
+        let k = if k == self.prime { 0 } else { k };
+        let plan = &self.plan;
+        let j = plan.starts.partition_point(|&start| start <= k) - 1;
+        self.words[j] -= 1 << (k - plan.starts[j]);
+    }
+}
+
+// Works in limbs, so it takes any Bignum
+impl<N: Bignum> Squarer<N> for Ibdwt {
+    fn set(&mut self, value: &N) {
+        self.set_limbs(&value.to_limbs());
+    }
+
+    fn get(&mut self, value: &mut N) {
+        *value = N::from_limbs(&self.limbs());
+    }
+
+    fn square(&mut self) -> Result<(), Stopped> {
+        Ibdwt::square(self)
+    }
+
+    fn sub_power_of_two(&mut self, k: u32) {
+        Ibdwt::sub_power_of_two(self, k);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::bignum::Integer;
+    use common::mersenne::Mersenne;
+
+    fn get(engine: &mut Ibdwt) -> Integer {
+        let mut x = Integer::from_u32(0);
+        engine.get(&mut x);
+        x
+    }
+
+    #[test]
+    fn squares_like_integers() {
+        for &p in &[2, 3, 31, 61, 89, 521, 4423, 21701] {
+            let mut m = Mersenne::new(p);
+            let mut engine = Ibdwt::new(p);
+            let mut x = Integer::from_u32(3);
+            engine.set(&x);
+            for i in 0..50 {
+                m.square(&mut x);
+                engine.square().unwrap();
+                if i % 7 == 0 {
+                    m.sub_power_of_two(&mut x, i % p);
+                    engine.sub_power_of_two(i % p);
+                }
+                assert_eq!(get(&mut engine), x, "M{} after {} squarings", p, i + 1);
+            }
+            assert!(engine.max_roundoff < MAX_ROUNDOFF);
+        }
+    }
+
+    #[test]
+    fn converts_both_ways() {
+        let p = 1279;
+        let mut engine = Ibdwt::new(p);
+        let mut m = Mersenne::new(p);
+        let mut top = Integer::from_u32(1);
+        m.mul_power_of_two(&mut top, p - 1);
+        for value in [Integer::from_u32(0), Integer::from_u32(1), top, m.modulus.clone()] {
+            engine.set(&value);
+            let mut reduced = value;
+            m.reduce(&mut reduced);
+            assert_eq!(get(&mut engine), reduced);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_a_longer_transform() {
+        // Words of 40 bits are far too wide for squares to come out exact
+        let p = 4423;
+        let mut m = Mersenne::new(p);
+        let mut engine = Ibdwt::with_len(p, 128);
+        let mut x = Integer::from_u32(3);
+        engine.set(&x);
+        for _ in 0..20 {
+            m.square(&mut x);
+            engine.square().unwrap();
+        }
+        assert!(engine.len() > 128);
+        assert_eq!(get(&mut engine), x);
+
+        // Words no carry has been through, as a broken FPU might leave them, at
+        // the longest length there is
+        let mut engine = Ibdwt::with_len(31, 16);
+        engine.words.fill(1 << 40);
+        assert_eq!(engine.square(), Err(Stopped::Unreliable));
+    }
+}
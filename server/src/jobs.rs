@@ -0,0 +1,436 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use common::json::{self, Value};
+
+use crate::prime_generator;
+
+// Largest exponent a job may ask for; LL tests beyond this would run for years
+const MAX_EXPONENT: u32 = 1_000_000_000;
+
+// Ranges are capped as all of a job's primes are held in memory while it runs
+const MAX_RANGE: u32 = 100_000_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum JobSpec {
+    Range(u32, u32),
+    Exponents(Vec<u32>),
+}
+
+impl JobSpec {
+    // Accepts {"lower": a, "upper": b} or {"exponents": [p, q, ...]}
+    pub fn from_json(body: &str) -> Result<JobSpec, String> {
+        let value = json::parse(body)?;
+        if let Some(exponents) = value.get("exponents") {
+            let items = exponents.as_array().ok_or("\"exponents\" must be an array")?;
+            let exponents: Option<Vec<u32>> = items.iter().map(Value::as_u32).collect();
+            let exponents = exponents.ok_or("exponents must be non-negative integers")?;
+            if exponents.is_empty() {
+                return Err(String::from("\"exponents\" is empty"));
+            }
+            return Ok(JobSpec::Exponents(exponents));
+        }
+
+        let bound = |key: &str| {
+            value
+                .get(key)
+                .and_then(Value::as_u32)
+                .ok_or(format!("\"{}\" must be a non-negative integer", key))
+        };
+        let lower = bound("lower")?;
+        let upper = bound("upper")?;
+        if lower > upper {
+            return Err(format!("empty range [{},{}]", lower, upper));
+        }
+        Ok(JobSpec::Range(lower, upper))
+    }
+
+    // An error naming what is not acceptable about a submitted job. Ranges only
+    // have their bounds checked, their primes are left to the job runner.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            JobSpec::Range(lower, upper) => {
+                if *upper > MAX_EXPONENT || upper - lower > MAX_RANGE {
+                    return Err(format!(
+                        "ranges are limited to {} exponents below {}",
+                        MAX_RANGE, MAX_EXPONENT
+                    ));
+                }
+                // The prime generator passes 0 and 1 through as if they were prime
+                if *lower < 2 {
+                    return Err(String::from("ranges must start at 2 or above"));
+                }
+                Ok(())
+            }
+            JobSpec::Exponents(exponents) => {
+                if let Some(too_big) = exponents.iter().find(|&&p| p > MAX_EXPONENT) {
+                    return Err(format!("exponent {} is above {}", too_big, MAX_EXPONENT));
+                }
+                let composite: Vec<u32> = exponents
+                    .iter()
+                    .copied()
+                    .filter(|&p| p < 2 || prime_generator::generate_primes_gen(p, p).is_empty())
+                    .collect();
+                if !composite.is_empty() {
+                    return Err(format!(
+                        "exponents must be prime, these are not: {}",
+                        json::array(&composite)
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // The prime exponents to test in ascending order. For a range up to
+    // MAX_RANGE long this takes a while, so it is only done once the job starts.
+    pub fn exponents(&self) -> Vec<u32> {
+        match self {
+            JobSpec::Range(lower, upper) => prime_generator::generate_primes_gen(*lower, *upper),
+            JobSpec::Exponents(exponents) => {
+                let mut exponents = exponents.clone();
+                exponents.sort();
+                exponents.dedup();
+                exponents
+            }
+        }
+    }
+
+    fn to_json(&self) -> String {
+        match self {
+            JobSpec::Range(lower, upper) => {
+                format!("{{\"lower\": {}, \"upper\": {}}}", lower, upper)
+            }
+            JobSpec::Exponents(exponents) => {
+                format!("{{\"exponents\": {}}}", json::array(exponents))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Cancelling,
+    Cancelled,
+    Done,
+}
+
+impl JobStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Cancelling => "cancelling",
+            JobStatus::Cancelled => "cancelled",
+            JobStatus::Done => "done",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub id: u32,
+    pub spec: JobSpec,
+    // Shared with the worker threads once the job has started, None before
+    pub exponents: Option<Arc<Vec<u32>>>,
+    pub status: JobStatus,
+    pub checked: usize,
+    pub found: Vec<u32>,
+    // Exponents whose error checks kept failing, neither prime nor composite
+    pub skipped: Vec<u32>,
+    // Work of all exponents and of those tested so far, each weighing p^slope
+    pub cost: f64,
+    pub cost_done: f64,
+    pub started: Option<Instant>,
+    pub took: Option<Duration>,
+    // Set to make the workers abandon this job at their next safe point
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl Job {
+    pub fn to_json(&self) -> String {
+        let mut found = self.found.clone();
+        found.sort();
+        let mut skipped = self.skipped.clone();
+        skipped.sort();
+
+        let count = match &self.exponents {
+            Some(exponents) => exponents.len().to_string(),
+            None => String::from("null"),
+        };
+        let elapsed = match (self.took, self.started) {
+            (Some(took), _) => json::f64(took.as_secs_f64()),
+            (None, Some(started)) => json::f64(started.elapsed().as_secs_f64()),
+            (None, None) => String::from("null"),
+        };
+
+        format!(
+            "{{\"id\": {}, \"status\": {}, \"spec\": {}, \"exponent_count\": {}, \
+             \"checked\": {}, \"elapsed_secs\": {}, \"found\": {}, \"skipped\": {}}}",
+            self.id,
+            json::string(self.status.name()),
+            self.spec.to_json(),
+            count,
+            self.checked,
+            elapsed,
+            json::array(&found),
+            json::array(&skipped)
+        )
+    }
+}
+
+// Every job ever submitted, in submission order. Jobs run one at a time, oldest first;
+// finished ones are kept so their results can still be queried.
+
+// An exponent p is taken to cost p^slope to test. The slope is fixed for the
+// queue's lifetime, so the sums of each job only change when an exponent completes.
+
+pub struct JobQueue {
+    jobs: Mutex<Vec<Job>>,
+    submitted: Condvar,
+    slope: f64,
+}
+
+impl JobQueue {
+    pub fn new(slope: f64) -> JobQueue {
+        JobQueue {
+            jobs: Mutex::new(vec![]),
+            submitted: Condvar::new(),
+            slope,
+        }
+    }
+
+    pub fn slope(&self) -> f64 {
+        self.slope
+    }
+
+    fn cost(&self, exponent: u32) -> f64 {
+        (exponent as f64).powf(self.slope)
+    }
+
+    pub fn submit(&self, spec: JobSpec) -> u32 {
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = jobs.len() as u32 + 1;
+        jobs.push(Job {
+            id,
+            spec,
+            exponents: None,
+            status: JobStatus::Queued,
+            checked: 0,
+            found: vec![],
+            skipped: vec![],
+            cost: 0.0,
+            cost_done: 0.0,
+            started: None,
+            took: None,
+            cancel: Arc::new(AtomicBool::new(false)),
+        });
+        self.submitted.notify_all();
+        id
+    }
+
+    // Blocks until a job is queued, marks it running and returns (id, exponents, cancel flag).
+    // The exponents are worked out here, on the job runner, without holding the lock.
+    pub fn next_job(&self) -> (u32, Arc<Vec<u32>>, Arc<AtomicBool>) {
+        let (id, spec, cancel) = {
+            let mut jobs = self.jobs.lock().unwrap();
+            loop {
+                if let Some(job) = jobs.iter_mut().find(|job| job.status == JobStatus::Queued) {
+                    job.status = JobStatus::Running;
+                    job.started = Some(Instant::now());
+                    break (job.id, job.spec.clone(), Arc::clone(&job.cancel));
+                }
+                jobs = self.submitted.wait(jobs).unwrap();
+            }
+        };
+        let exponents = Arc::new(spec.exponents());
+        let cost = exponents.iter().map(|&p| self.cost(p)).sum();
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+            job.exponents = Some(Arc::clone(&exponents));
+            job.cost = cost;
+        }
+        (id, exponents, cancel)
+    }
+
+    // None for an exponent that was skipped rather than tested
+    pub fn record(&self, id: u32, exponent: u32, is_prime: Option<bool>) {
+        let cost = self.cost(exponent);
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+            job.cost_done += cost;
+            match is_prime {
+                Some(is_prime) => {
+                    job.checked += 1;
+                    if is_prime {
+                        job.found.push(exponent);
+                    }
+                }
+                None => job.skipped.push(exponent),
+            }
+        }
+    }
+
+    pub fn finish(&self, id: u32) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+            job.status = if job.cancel.load(Ordering::SeqCst) {
+                JobStatus::Cancelled
+            } else {
+                JobStatus::Done
+            };
+            job.took = job.started.map(|started| started.elapsed());
+        }
+    }
+
+    // A queued job is dropped at once; a running one is flagged and stays
+    // "cancelling" until its workers reach a safe point and finish() is called
+    pub fn cancel(&self, id: u32) -> Result<JobStatus, String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs
+            .iter_mut()
+            .find(|job| job.id == id)
+            .ok_or(format!("no job #{}", id))?;
+        match job.status {
+            JobStatus::Queued => job.status = JobStatus::Cancelled,
+            JobStatus::Running => job.status = JobStatus::Cancelling,
+            status => return Err(format!("job #{} is already {}", id, status.name())),
+        }
+        job.cancel.store(true, Ordering::SeqCst);
+        Ok(job.status)
+    }
+
+    // The job the workers are on, if any
+    pub fn current(&self) -> Option<Job> {
+        let jobs = self.jobs.lock().unwrap();
+        let mut active = jobs
+            .iter()
+            .filter(|job| matches!(job.status, JobStatus::Running | JobStatus::Cancelling));
+        active.next().cloned()
+    }
+
+    pub fn get(&self, id: u32) -> Option<Job> {
+        self.jobs.lock().unwrap().iter().find(|job| job.id == id).cloned()
+    }
+
+    pub fn all(&self) -> Vec<Job> {
+        self.jobs.lock().unwrap().clone()
+    }
+
+    // Exponents over all jobs, the denominator of "% done". Queued jobs only
+    // count once they start, when their exponents are known.
+    pub fn total_exponents(&self) -> u32 {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.iter().flat_map(|job| &job.exponents).map(|exponents| exponents.len() as u32).sum()
+    }
+
+    // Work over all jobs as (done, still to do), see `slope`.
+    // Finished and cancelled jobs have nothing left to do.
+    pub fn work(&self) -> (f64, f64) {
+        let jobs = self.jobs.lock().unwrap();
+        let mut done = 0.0;
+        let mut left = 0.0;
+        for job in jobs.iter() {
+            done += job.cost_done;
+            if matches!(job.status, JobStatus::Queued | JobStatus::Running) {
+                left += (job.cost - job.cost_done).max(0.0);
+            }
+        }
+        (done, left)
+    }
+
+    pub fn biggest_exponent(&self) -> u32 {
+        let jobs = self.jobs.lock().unwrap();
+        let biggest = jobs.iter().flat_map(|job| &job.exponents).filter_map(|exponents| exponents.last());
+        biggest.copied().max().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_specs() {
+        assert_eq!(
+            JobSpec::from_json(r#"{"lower": 100, "upper": 200}"#),
+            Ok(JobSpec::Range(100, 200))
+        );
+        assert_eq!(
+            JobSpec::from_json(r#"{"exponents": [521, 127]}"#),
+            Ok(JobSpec::Exponents(vec![521, 127]))
+        );
+        assert!(JobSpec::from_json(r#"{"lower": 200, "upper": 100}"#).is_err());
+        assert!(JobSpec::from_json(r#"{"exponents": []}"#).is_err());
+        assert!(JobSpec::from_json(r#"{"exponents": [1.5]}"#).is_err());
+        assert!(JobSpec::from_json(r#"{"upper": 100}"#).is_err());
+    }
+
+    #[test]
+    fn exponents_must_be_prime() {
+        let spec = JobSpec::Exponents(vec![127, 15, 521, 127]);
+        assert!(spec.validate().unwrap_err().contains("[15]"));
+        let spec = JobSpec::Exponents(vec![521, 127, 521]);
+        assert_eq!((spec.validate(), spec.exponents()), (Ok(()), vec![127, 521]));
+        assert_eq!(JobSpec::Range(10, 20).exponents(), vec![11, 13, 17, 19]);
+        assert!(JobSpec::Range(2, 200_000_000).validate().is_err());
+        assert!(JobSpec::Exponents(vec![0]).validate().unwrap_err().contains("[0]"));
+        assert!(JobSpec::Exponents(vec![127, 1]).validate().unwrap_err().contains("[1]"));
+        assert!(JobSpec::Range(0, 20).validate().is_err());
+        assert!(JobSpec::Range(1, 20).validate().is_err());
+        assert_eq!(JobSpec::Range(2, 20).validate(), Ok(()));
+    }
+
+    #[test]
+    fn jobs_run_in_order() {
+        let queue = JobQueue::new(1.0);
+        let first = queue.submit(JobSpec::Range(2, 10));
+        let second = queue.submit(JobSpec::Exponents(vec![13]));
+        assert_eq!(queue.get(first).unwrap().exponents, None);
+
+        let (id, exponents, _) = queue.next_job();
+        assert_eq!((id, exponents.to_vec()), (first, vec![2, 3, 5, 7]));
+        queue.record(first, 3, Some(true));
+        queue.record(first, 5, Some(false));
+        queue.record(first, 7, None);
+        queue.finish(first);
+        let (id, exponents, _) = queue.next_job();
+        assert_eq!((id, exponents.to_vec()), (second, vec![13]));
+
+        let job = queue.get(first).unwrap();
+        assert_eq!(job.status, JobStatus::Done);
+        assert_eq!((job.checked, job.found.clone(), job.skipped.clone()), (2, vec![3], vec![7]));
+        assert!(job.to_json().contains("\"found\": [3], \"skipped\": [7]"));
+        assert_eq!(queue.get(second).unwrap().status, JobStatus::Running);
+        assert_eq!(queue.total_exponents(), 5);
+        // Job one is done, job two has 13 left
+        assert_eq!(queue.work(), (15.0, 13.0));
+        assert_eq!(queue.biggest_exponent(), 13);
+    }
+
+    #[test]
+    fn cancel_queued_and_running_jobs() {
+        let queue = JobQueue::new(1.0);
+        let first = queue.submit(JobSpec::Exponents(vec![3]));
+        let second = queue.submit(JobSpec::Exponents(vec![5]));
+        let third = queue.submit(JobSpec::Exponents(vec![7]));
+
+        let (id, _, cancel) = queue.next_job();
+        assert_eq!(id, first);
+        assert_eq!(queue.cancel(second), Ok(JobStatus::Cancelled));
+        assert_eq!(queue.cancel(first), Ok(JobStatus::Cancelling));
+        assert!(cancel.load(Ordering::SeqCst));
+        assert_eq!(queue.current().unwrap().id, first);
+
+        queue.finish(first);
+        assert_eq!(queue.get(first).unwrap().status, JobStatus::Cancelled);
+        assert!(queue.cancel(first).is_err());
+        assert!(queue.cancel(42).is_err());
+
+        // The cancelled job is skipped
+        assert_eq!(queue.next_job().0, third);
+    }
+}
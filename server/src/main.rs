@@ -1,104 +1,494 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Sender};
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Instant;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 // Local files
 
-mod args;
+mod activity;
+mod assets;
 mod console;
+mod control;
+mod costmodel;
+mod events;
+mod http;
+mod ibdwt;
+mod jobs;
+mod metrics;
+mod pm1;
+mod pool;
 mod prime_generator;
 mod primes;
+mod schedule;
 mod server;
-use server::ServerPayload;
+mod signals;
+mod websocket;
+use activity::Activity;
+use common::args;
+use common::checkpoint::Checkpoints;
+use common::factor::{self, Factoring};
+use common::ledger::{Check, Ledger, Record};
+use control::Control;
+use costmodel::CostModel;
+use events::{Event, EventHub};
+use jobs::{JobQueue, JobSpec};
+use metrics::Histogram;
+use pm1::{Bounds, Pm1};
+use pool::ConnectionStats;
+use primes::{Engine, ErrorChecks, Residue, Stopped, Test};
+use schedule::{Order, WorkQueue};
+use server::{ServerConfig, ServerPayload, ServerState};
 
-// Constants
+// Everything the worker threads share with each other and with the server
 
-const N_THREADS: usize = 1;
+#[derive(Clone)]
+struct WorkerState {
+    send: Sender<u32>,
+    found_mersennes: Arc<Mutex<Vec<u32>>>,
+    checked_count: Arc<Mutex<Vec<u32>>>,
+    events: Arc<EventHub>,
+    control: Arc<Control>,
+    durations: Arc<Histogram>,
+    activity: Arc<Activity>,
+    checks: Arc<ErrorChecks>,
+    costs: Arc<CostModel>,
+    jobs: Arc<JobQueue>,
+    checkpoints: Arc<Checkpoints>,
+    ledger: Arc<Ledger>,
+    order: Order,
+    test: Test,
+    engine: Engine,
+    // The most threads one squaring is split over once there are more workers
+    // than exponents left in a job (--square-threads, 1 to never split), None for no limit
+    square_threads: Option<usize>,
+    // Rerun LL tests with a shift instead of testing anything new (--mode=double-check)
+    double_check: bool,
+    // Trial factoring depth in bits, None for factor::default_depth and 0 to skip it
+    tf_depth: Option<u32>,
+    // P-1 bounds, None to pick them per exponent and B1 = 0 to skip P-1
+    pm1_bounds: Option<Bounds>,
+}
 
-// A main worker thread
+// How often the job runner looks for finished workers and a changed worker count
+const WORKER_POLL: Duration = Duration::from_millis(100);
 
-fn spawn_threads(
-    send: Sender<u32>,
-    found_mersennes: &Arc<Mutex<Vec<u32>>>,
-    checked_count: &Arc<Mutex<Vec<u32>>>,
-    primes: Vec<u32>,
-) {
-    for i in 0..N_THREADS {
-        let sender = send.clone();
-        let primes = primes.clone();
-        let found_mersennes_clone = Arc::clone(found_mersennes);
-        let checked_count_clone = Arc::clone(checked_count);
+// Trial factoring, then P-1, each unless an earlier run already got as far.
+// Says whether a factor turned up, or None if the job was cancelled.
+fn find_factor(state: &WorkerState, prime: u32, cancel: &AtomicBool) -> Option<bool> {
+    let mut keep_going = || state.control.safe_point(cancel);
+    let test_start = Instant::now();
+    let depth = state.tf_depth.unwrap_or_else(|| factor::default_depth(prime));
+    if !state.ledger.factored_to(prime, depth) {
+        let factor = match factor::trial_factor(prime, depth, &mut keep_going)? {
+            Factoring::Factor(q) => Some(q.to_string()),
+            Factoring::NoFactor => None,
+        };
+        let found = factor.is_some();
+        state.ledger.append(&Record {
+            depth: Some(depth),
+            ..Record::factor(prime, "TF", factor, test_start.elapsed())
+        });
+        if found {
+            return Some(true);
+        }
+    }
 
-        thread::spawn(move || {
-            let mut k = i;
+    let bounds = state.pm1_bounds.or_else(|| pm1::choose_bounds(prime, depth.min(64)));
+    let bounds = match bounds.filter(|bounds| bounds.b1 > 0) {
+        Some(bounds) => bounds,
+        None => return Some(false),
+    };
+    // Covered only by one run with both bounds at least as large
+    let tried = state.ledger.pm1_bounds(prime);
+    if tried.into_iter().any(|(b1, b2)| Bounds { b1, b2 }.covers(bounds)) {
+        return Some(false);
+    }
+    let test_start = Instant::now();
+    let factor = match pm1::p_minus_1(prime, bounds, &mut keep_going)? {
+        Pm1::Factor(q) => Some(q.to_string()),
+        Pm1::NoFactor => None,
+    };
+    let found = factor.is_some();
+    state.ledger.append(&Record {
+        bounds: Some((bounds.b1, bounds.b2)),
+        ..Record::factor(prime, "PM1", factor, test_start.elapsed())
+    });
+    Some(found)
+}
+
+// Tests one exponent: looks for a factor first, then runs Lucas-Lehmer (or PRP)
+// unless one turned up. An exponent whose factor is already in the ledger is not tested
+// at all, and one with a checkpoint is past factoring. Double-checks skip factoring
+// and run LL from a random shift. The tests stop at their safe points when paused,
+// and a cancelled job gives None. One whose error checks keep failing is left
+// unrecorded and gives Some(None), neither prime nor composite.
+// Once the job's last exponents are handed out, its idle workers help with their squarings.
+fn test_exponent(
+    state: &WorkerState,
+    prime: u32,
+    slot: usize,
+    work: &WorkQueue,
+    cancel: &AtomicBool,
+) -> Option<Option<bool>> {
+    // 2 is a mersenne prime, but it fails the tests
+    if prime == 2 {
+        return Some(Some(true));
+    }
+    if state.ledger.factor(prime).is_some() {
+        return Some(Some(false));
+    }
+
+    let checkpoint = state.checkpoints.load(prime);
+    if checkpoint.is_none() && !state.double_check && find_factor(state, prime, cancel)? {
+        return Some(Some(false));
+    }
+
+    let test_start = Instant::now();
+    // Resumed or paused tests say little about how long a test takes
+    let mut interrupted = false;
+    let start = checkpoint.unwrap_or_else(|| match state.double_check {
+        true => Residue::shifted_start(prime, primes::random_shift(prime)),
+        false => state.test.start(),
+    });
+    let shift = start.initial_shift(prime);
+    if start.iteration > 0 {
+        println!("Resuming M{} from iteration {}", prime, start.iteration);
+        interrupted = true;
+    }
+    let iterations = state.test.iterations(prime);
+    let counter = state.activity.begin(slot, prime, start.iteration, iterations);
+    let mut saver = state.checkpoints.saver(prime);
+    // Pauses happen between iterations; a cancelled test is not recorded.
+    // Saving before a pause means a paused run can be stopped without losing work.
+    let mut safe_point = |residue: &Residue, iteration: u32| {
+        let paused = state.control.is_paused();
+        interrupted |= paused;
+        counter.store(iteration, Ordering::Relaxed);
+        saver.tick(residue, paused);
+        state.control.safe_point(cancel)
+    };
+    let threads = || {
+        let threads = work.threads_per_test(state.control.threads());
+        state.square_threads.map_or(threads, |max| threads.min(max))
+    };
+    let result = state.engine.run(state.test, prime, start, &threads, &state.checks, &mut safe_point);
+    state.activity.end(slot);
+    let outcome = match result {
+        Ok(outcome) => outcome,
+        Err(Stopped::Abandoned) => return None,
+        // The checkpoint stays, holding the last state that passed a check
+        Err(Stopped::Unreliable) => {
+            eprintln!("M{} skipped, its arithmetic cannot be trusted on this machine", prime);
+            return Some(None);
+        }
+    };
+
+    let duration = test_start.elapsed();
+    state.checkpoints.remove(prime);
+    let record = Record {
+        shift: Some(shift).filter(|&shift| shift > 0),
+        ..Record::new(prime, outcome, state.test.name(), duration)
+    };
+    let check = state.ledger.append(&record);
+    if state.double_check {
+        report_double_check(prime, outcome.res64, check);
+    }
+    state.durations.observe(duration);
+    if !interrupted {
+        state.costs.observe(prime, duration);
+    }
+    Some(Some(outcome.is_prime))
+}
+
+// A mismatch leaves the exponent unverified in the ledger, so the next
+// double-check run takes it up again as the third run it needs
+fn report_double_check(prime: u32, res64: u64, check: Check) {
+    match check {
+        Check::Matches => println!("M{} double-checked, res64 {:016x} matches", prime, res64),
+        Check::Repeated => {
+            println!("M{} matches only a run with the same shift, it still needs a check", prime)
+        }
+        Check::Mismatch(_) => eprintln!("M{} does not match the ledger, it needs a third run", prime),
+        Check::New => println!("M{} had no earlier result to double-check", prime),
+    }
+}
 
-            while k < primes.len() {
-                let prime = primes[k];
-                if primes::is_mersenne_prime(prime) {
-                    sender.send(prime).unwrap();
-                    let mut vec = found_mersennes_clone.lock().unwrap();
+// A main worker thread. It takes exponents off `work` until there are none left,
+// the job is cancelled, or the worker count drops to `slot` or below.
+
+fn spawn_worker(
+    state: &WorkerState,
+    job_id: u32,
+    slot: usize,
+    work: &Arc<WorkQueue>,
+    cancel: &Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    let work = Arc::clone(work);
+    let state = state.clone();
+    let cancel = Arc::clone(cancel);
+
+    thread::spawn(move || {
+        while slot < state.control.threads() {
+            let prime = match work.next() {
+                Some(prime) => prime,
+                None => break,
+            };
+            let test_start = Instant::now();
+            let result = match test_exponent(&state, prime, slot, &work, &cancel) {
+                Some(result) => result,
+                None => break,
+            };
+            work.finish();
+            state.jobs.record(job_id, prime, result);
+            // A skipped exponent is neither checked nor found
+            let is_prime = match result {
+                Some(is_prime) => is_prime,
+                None => continue,
+            };
+            if is_prime {
+                state.send.send(prime).unwrap();
+                let mut vec = state.found_mersennes.lock().unwrap();
+                // Jobs may overlap, but each prime is only found once
+                if !vec.contains(&prime) {
                     vec.push(prime);
                 }
-                let mut counts = checked_count_clone.lock().unwrap();
-                counts[i] += 1;
-                k += N_THREADS;
+                state.events.publish(Event::found(prime));
             }
-        });
+            let millis = test_start.elapsed().as_millis();
+            state.checked_count.lock().unwrap()[slot] += 1;
+            state.events.publish(Event::checked(prime, slot, is_prime, millis));
+        }
+    })
+}
+
+// Runs one job on as many workers as `control.threads()` asks for, following
+// changes to it: missing workers are started right away, surplus ones stop
+// once their current exponent is done.
+// Threads take the next exponent when they are free, so none sits idle
+// while others still have a backlog.
+fn run_job(state: &WorkerState, job_id: u32, primes: Arc<Vec<u32>>, cancel: Arc<AtomicBool>) {
+    let work = Arc::new(WorkQueue::new(primes, state.order));
+    let mut workers: Vec<Option<JoinHandle<()>>> = vec![];
+
+    loop {
+        let threads = state.control.threads();
+        let more_to_do = !work.is_exhausted() && !cancel.load(Ordering::SeqCst);
+
+        // Reap finished workers, restarting those that are still wanted
+        for slot in 0..workers.len().max(threads) {
+            if slot == workers.len() {
+                workers.push(None);
+            }
+            if workers[slot].as_ref().is_some_and(|worker| worker.is_finished()) {
+                workers[slot].take().unwrap().join().unwrap();
+            }
+            if workers[slot].is_none() && slot < threads && more_to_do {
+                let mut counts = state.checked_count.lock().unwrap();
+                if counts.len() <= slot {
+                    counts.resize(slot + 1, 0);
+                }
+                drop(counts);
+                workers[slot] = Some(spawn_worker(state, job_id, slot, &work, &cancel));
+            }
+        }
+
+        if workers.iter().all(Option::is_none) && !more_to_do {
+            break;
+        }
+        thread::sleep(WORKER_POLL);
+    }
+}
+
+// Runs the jobs one after another, each split over all the worker threads.
+// More can be submitted over HTTP at any time, so this never returns.
+fn run_jobs(state: WorkerState) {
+    loop {
+        let (job_id, primes, cancel) = state.jobs.next_job();
+        println!(
+            "Starting job #{} with {} primes on {} worker threads, {} {}...",
+            job_id,
+            primes.len(),
+            state.control.threads(),
+            state.test.name(),
+            state.order.name()
+        );
+        run_job(&state, job_id, primes, cancel);
+        state.jobs.finish(job_id);
+        state.costs.save();
+        let status = state.jobs.get(job_id).unwrap().status;
+        println!("Job #{} {}", job_id, status.name());
     }
 }
 
 // Putting it all together
 
 fn main() {
-    // Generate the primes from the given span
-    println!("Generating primes...");
+    // The primes of the given span are generated once its job starts
     let (lower_bound, upper_bound) = args::parse_cmd_args();
     println!("Looking for Mersenne primes in [{},{}]", lower_bound, upper_bound);
 
-    let primes = prime_generator::generate_primes_gen(lower_bound, upper_bound);
-
-    let prime_count = primes.len() as u32;
-    let biggest = primes[primes.len() - 1];
-    println!("Generated {} primes...", prime_count);
+    let order = args::get_flag("order").unwrap_or_else(|| String::from("largest-first"));
+    let order = Order::parse(&order).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    });
+    // --test=ll (the default) or prp
+    let test = args::get_flag("test").unwrap_or_else(|| String::from("ll"));
+    let test = Test::parse(&test).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    });
+    // --engine=integer (the default) or ibdwt
+    let engine = args::get_setting("engine").unwrap_or_else(|| String::from("integer"));
+    let engine = Engine::parse(&engine).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    });
+    // --mode=first-time (the default) or double-check, which reruns the LL tests
+    // in the ledger that no other run has reproduced yet
+    let double_check = match args::get_flag("mode").as_deref() {
+        None | Some("first-time") => false,
+        Some("double-check") if test == Test::LucasLehmer => true,
+        Some("double-check") => {
+            eprintln!("Double-checks are Lucas-Lehmer tests, they cannot be combined with --test");
+            std::process::exit(1);
+        }
+        Some(mode) => {
+            eprintln!("unknown mode {}, expected first-time or double-check", mode);
+            std::process::exit(1);
+        }
+    };
 
     // Initialize synchronization channels and mutexes
     let (send, recv) = channel();
     let found_mersennes = Arc::new(Mutex::new(vec![]));
-    let checked_count = Arc::new(Mutex::new(vec![0; N_THREADS]));
+    // One worker per core unless --threads, MERSENNE_THREADS or the config file say otherwise
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    let threads = args::get_setting_u32("threads", cores as u32) as usize;
+    let control = Arc::new(Control::new(threads));
+    let checked_count = Arc::new(Mutex::new(vec![0; control.threads()]));
+    let events = Arc::new(EventHub::new());
+    let durations = Arc::new(Histogram::for_durations());
+    let activity = Arc::new(Activity::new());
+    // LL tests check their residue every --jacobi-interval iterations (0 turns that off)
+    let checks = Arc::new(ErrorChecks::new(args::get_setting_u32("jacobi-interval", 10_000)));
+    let cost_model = args::get_flag("cost-model").unwrap_or_else(|| String::from("cost-model.txt"));
+    let costs = Arc::new(CostModel::load(Some(PathBuf::from(cost_model))));
+    let jobs = Arc::new(JobQueue::new(costs.slope()));
+    let checkpoints = Checkpoints::new(
+        PathBuf::from(args::get_flag("checkpoint-dir").unwrap_or_else(|| String::from("checkpoints"))),
+        Duration::from_secs(args::get_flag_u32("checkpoint-interval", 600) as u64),
+        test,
+    );
+    let checkpoints = Arc::new(match double_check {
+        true => checkpoints.for_double_checks(),
+        false => checkpoints,
+    });
+    let ledger_path = args::get_flag("ledger").unwrap_or_else(|| String::from("results.jsonl"));
+    let ledger = Arc::new(Ledger::open(Path::new(&ledger_path)).expect("Could not open the ledger"));
+    println!("Ledger {} holds {} earlier results", ledger_path, ledger.known_results());
 
-    // 2 is a mersenne prime, but it fails the tests
-    if lower_bound <= 2 && 2 <= upper_bound {
-        let mut m = found_mersennes.lock().unwrap();
-        m.push(2);
-        send.send(2).unwrap();
+    // The command line range is simply the first job, in double-check mode
+    // the results to check are
+    if double_check {
+        let exponents = ledger.unverified(test.name());
+        println!("Double-checking {} results from the ledger", exponents.len());
+        jobs.submit(JobSpec::Exponents(exponents));
+    } else {
+        // 0 and 1 are not exponents of Mersenne numbers, whatever the command line says
+        jobs.submit(JobSpec::Range(lower_bound.max(2), upper_bound));
     }
 
-    // Spawn all the threads
-    println!("Spawning {} worker threads...", N_THREADS);
-    spawn_threads(send, &found_mersennes, &checked_count, primes);
+    let worker_state = WorkerState {
+        send,
+        found_mersennes: Arc::clone(&found_mersennes),
+        checked_count: Arc::clone(&checked_count),
+        events: Arc::clone(&events),
+        control: Arc::clone(&control),
+        durations: Arc::clone(&durations),
+        activity: Arc::clone(&activity),
+        checks: Arc::clone(&checks),
+        costs: Arc::clone(&costs),
+        jobs: Arc::clone(&jobs),
+        checkpoints,
+        ledger,
+        order,
+        test,
+        engine,
+        square_threads: args::get_setting("square-threads").and_then(|n| n.parse().ok()).filter(|&n| n > 0),
+        double_check,
+        tf_depth: args::get_setting("tf-depth").and_then(|depth| depth.parse().ok()),
+        pm1_bounds: args::get_setting("pm1-b1").and_then(|b1| b1.parse().ok()).map(|b1| Bounds {
+            b1,
+            b2: args::get_setting("pm1-b2").and_then(|b2| b2.parse().ok()).unwrap_or(20 * b1),
+        }),
+    };
+
+    println!("Listening for SIGUSR1 (pause/resume) and SIGUSR2 (cancel job)");
+    signals::spawn_watcher(Arc::clone(&control), Arc::clone(&jobs));
 
     println!("Spawning the console reporter");
-    let reporter = thread::spawn(move || {
-        console::console_reporter(recv);
-    });
+    let reporter = {
+        let (costs, jobs, control) = (Arc::clone(&costs), Arc::clone(&jobs), Arc::clone(&control));
+        let activity = Arc::clone(&activity);
+        thread::spawn(move || {
+            console::console_reporter(
+                recv,
+                || costs.progress(&jobs, control.threads()).describe(),
+                || {
+                    let snapshots = activity.snapshot().into_iter();
+                    snapshots.map(|s| format!("Thread {}: {}", s.slot + 1, s.describe())).collect()
+                },
+            );
+        })
+    };
 
     let start = Instant::now();
     let server_payload = ServerPayload {
-        prime_count,
-        biggest,
         start,
-        N_THREADS,
         lower_bound,
         upper_bound,
+        order,
+        test,
+        engine,
+    };
+
+    let server_config = ServerConfig {
+        pool_size: args::get_flag_u32("http-threads", 4) as usize,
+        queue_depth: args::get_flag_u32("http-queue", 16) as usize,
+        max_streams: args::get_flag_u32("http-streams", 16) as usize,
+        timeout: Duration::from_secs(args::get_flag_u32("http-timeout", 30) as u64),
+        www_dir: args::get_flag("www-dir").map(PathBuf::from),
     };
 
     println!("Spawning the server reporter");
+    let server_state = ServerState {
+        found_mersennes: Arc::clone(&found_mersennes),
+        checked_count: Arc::clone(&checked_count),
+        events: Arc::clone(&events),
+        control: Arc::clone(&control),
+        durations: Arc::clone(&durations),
+        activity: Arc::clone(&activity),
+        checks: Arc::clone(&checks),
+        costs: Arc::clone(&costs),
+        jobs: Arc::clone(&jobs),
+        connections: Arc::new(ConnectionStats::default()),
+        config: server_config,
+        payload: server_payload,
+    };
     let server = std::thread::spawn(move || {
-        server::server_main(&found_mersennes, &checked_count, server_payload).unwrap();
+        server::server_main(server_state).unwrap();
     });
 
+    // The job runner keeps a sender for future jobs, so with the server running
+    // the console reporter prints its final summary only once the process is stopped
+    println!("Spawning the job runner");
+    let runner = thread::spawn(move || run_jobs(worker_state));
+
     // Wait for the reports to finish (the server never really finishes though)
+    runner.join().unwrap();
     reporter.join().unwrap();
     server.join().unwrap();
 }
@@ -0,0 +1,210 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::server::ServerState;
+
+// Lucas-Lehmer tests take anywhere from microseconds to days, hence the wide buckets
+const DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.01, 0.1, 1.0, 10.0, 60.0, 300.0, 1800.0, 3600.0, 21600.0, 86400.0, 604800.0,
+];
+
+// A Prometheus-style histogram: per-bucket counts plus the running sum and count
+
+pub struct Histogram {
+    bounds: Vec<f64>,
+    state: Mutex<HistogramState>,
+}
+
+struct HistogramState {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &[f64]) -> Histogram {
+        Histogram {
+            bounds: bounds.to_vec(),
+            state: Mutex::new(HistogramState {
+                counts: vec![0; bounds.len()],
+                sum: 0.0,
+                count: 0,
+            }),
+        }
+    }
+
+    pub fn for_durations() -> Histogram {
+        Histogram::new(&DURATION_BUCKETS)
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let value = duration.as_secs_f64();
+        let mut state = self.state.lock().unwrap();
+        if let Some(bucket) = self.bounds.iter().position(|&bound| value <= bound) {
+            state.counts[bucket] += 1;
+        }
+        state.sum += value;
+        state.count += 1;
+    }
+
+    // Exposition lines for `name`, with cumulative buckets as Prometheus expects
+    fn render(&self, name: &str, out: &mut String) {
+        let state = self.state.lock().unwrap();
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(state.counts.iter()) {
+            cumulative += count;
+            out.push_str(&format!("{}_bucket{{le=\"{}\"}} {}\n", name, bound, cumulative));
+        }
+        out.push_str(&format!("{}_bucket{{le=\"+Inf\"}} {}\n", name, state.count));
+        out.push_str(&format!("{}_sum {}\n", name, state.sum));
+        out.push_str(&format!("{}_count {}\n", name, state.count));
+    }
+}
+
+fn describe(out: &mut String, name: &str, kind: &str, help: &str) {
+    out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: impl ToString) {
+    describe(out, name, kind, help);
+    out.push_str(&format!("{} {}\n", name, value.to_string()));
+}
+
+// Prometheus text exposition format (version 0.0.4), read from the same state as /count
+pub fn generate_metrics(state: &ServerState) -> String {
+    let found_count = state.found_mersennes.lock().unwrap().len();
+    let counts = state.checked_count.lock().unwrap().clone();
+    let sum: u64 = counts.iter().map(|&n| n as u64).sum();
+
+    let prime_count = state.jobs.total_exponents();
+    let elapsed = state.payload.start.elapsed().as_secs_f64();
+    let primes_per_second = if elapsed > 0.0 { sum as f64 / elapsed } else { 0.0 };
+
+    let mut out = String::new();
+
+    describe(
+        &mut out,
+        "mersenne_checked_total",
+        "counter",
+        "Exponents tested so far, per worker thread.",
+    );
+    for (i, n) in counts.iter().enumerate() {
+        out.push_str(&format!("mersenne_checked_total{{thread=\"{}\"}} {}\n", i + 1, n));
+    }
+
+    metric(
+        &mut out,
+        "mersenne_found_total",
+        "counter",
+        "Mersenne prime exponents found so far.",
+        found_count,
+    );
+    metric(
+        &mut out,
+        "mersenne_prime_count",
+        "gauge",
+        "Prime exponents over all submitted jobs.",
+        prime_count,
+    );
+    metric(
+        &mut out,
+        "mersenne_biggest_exponent",
+        "gauge",
+        "Largest exponent over all submitted jobs.",
+        state.jobs.biggest_exponent(),
+    );
+    metric(
+        &mut out,
+        "mersenne_elapsed_seconds",
+        "gauge",
+        "Seconds since the search started.",
+        elapsed,
+    );
+    metric(
+        &mut out,
+        "mersenne_primes_per_second",
+        "gauge",
+        "Average number of exponents tested per second.",
+        primes_per_second,
+    );
+
+    let progress = state.costs.progress(&state.jobs, state.control.threads());
+    metric(
+        &mut out,
+        "mersenne_work_done_ratio",
+        "gauge",
+        "Share of the estimated work over all jobs that is done.",
+        progress.fraction.unwrap_or(f64::NAN),
+    );
+    metric(
+        &mut out,
+        "mersenne_eta_seconds",
+        "gauge",
+        "Estimated seconds until all queued work is done.",
+        progress.eta_secs.unwrap_or(f64::NAN),
+    );
+
+    describe(
+        &mut out,
+        "mersenne_ll_duration_seconds",
+        "histogram",
+        "Wall time of a single Lucas-Lehmer test.",
+    );
+    state.durations.render("mersenne_ll_duration_seconds", &mut out);
+
+    metric(
+        &mut out,
+        "mersenne_check_failures_total",
+        "counter",
+        "Failed Jacobi and Gerbicz checks, each followed by a rollback.",
+        state.checks.failures(),
+    );
+
+    metric(
+        &mut out,
+        "mersenne_http_connections_active",
+        "gauge",
+        "HTTP connections being served by the pool.",
+        state.connections.active(),
+    );
+    metric(
+        &mut out,
+        "mersenne_http_connections_rejected_total",
+        "counter",
+        "HTTP connections turned away with a 503.",
+        state.connections.rejected(),
+    );
+    metric(
+        &mut out,
+        "mersenne_http_streams_open",
+        "gauge",
+        "Open /events and /ws connections.",
+        state.connections.streams(),
+    );
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new(&[1.0, 10.0]);
+        histogram.observe(Duration::from_millis(500));
+        histogram.observe(Duration::from_secs(5));
+        histogram.observe(Duration::from_secs(50));
+
+        let mut out = String::new();
+        histogram.render("test", &mut out);
+        assert_eq!(
+            out,
+            "test_bucket{le=\"1\"} 1\n\
+             test_bucket{le=\"10\"} 2\n\
+             test_bucket{le=\"+Inf\"} 3\n\
+             test_sum 55.5\n\
+             test_count 3\n"
+        );
+    }
+}
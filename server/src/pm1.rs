@@ -0,0 +1,271 @@
+use rug::Integer;
+
+// Pollard's P-1 method on M_p. Every factor q of M_p is 1 mod 2p, so when
+// q - 1 = 2p * k with k made of primes up to B1 (stage 1) plus at most one
+// prime up to B2 (stage 2), q divides gcd(3^E - 1, M_p) for E = 2p * lcm(1..B1),
+// times one more prime in stage 2.
+// Unlike trial factoring it finds factors of any size, as long as k is smooth.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub b1: u64,
+    pub b2: u64,
+}
+
+impl Bounds {
+    // Whether a run to these bounds finds everything a run to `other` would
+    pub fn covers(self, other: Bounds) -> bool {
+        self.b1 >= other.b1 && self.b2 >= other.b2
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pm1 {
+    Factor(Integer),
+    NoFactor,
+}
+
+// Calls `f` with every prime in [from, to] in increasing order, a segment at a time
+// so B2 can be large; stops as soon as `f` returns false, and says whether it did
+fn each_prime(from: u64, to: u64, f: &mut dyn FnMut(u64) -> bool) -> bool {
+    const SEGMENT: u64 = 1 << 16;
+    let mut base: Vec<u64> = vec![];
+    let mut n = 2;
+    while n * n <= to {
+        if base.iter().take_while(|&&r| r * r <= n).all(|&r| n % r != 0) {
+            base.push(n);
+        }
+        n += 1;
+    }
+
+    let mut low = from.max(2);
+    while low <= to {
+        let high = (low + SEGMENT - 1).min(to);
+        let mut composite = vec![false; (high - low + 1) as usize];
+        for &r in &base {
+            let mut multiple = (low.div_ceil(r) * r).max(r * r);
+            while multiple <= high {
+                composite[(multiple - low) as usize] = true;
+                multiple += r;
+            }
+        }
+        for (i, _) in composite.iter().enumerate().filter(|(_, &c)| !c) {
+            if !f(low + i as u64) {
+                return false;
+            }
+        }
+        low = high + 1;
+    }
+    true
+}
+
+// A factor strictly between 1 and M_p; when every factor shows up at once there is nothing to report
+fn proper_factor(g: Integer, modulus: &Integer) -> Option<Integer> {
+    if g > 1 && &g != modulus {
+        Some(g)
+    } else {
+        None
+    }
+}
+
+// Runs both stages. `keep_going` is asked between primes; once it says no
+// the run is abandoned and the result is None.
+pub fn p_minus_1(prime: u32, bounds: Bounds, keep_going: &mut dyn FnMut() -> bool) -> Option<Pm1> {
+    let mut modulus = Integer::from(1) << prime;
+    modulus -= 1;
+
+    // Stage 1: x = 3^(2p * prod q^e) with q^e the largest power of q up to B1
+    let start = Integer::from(2 * prime as u64);
+    let mut x = Integer::from(3).pow_mod(&start, &modulus).unwrap();
+    let finished = each_prime(2, bounds.b1, &mut |q| {
+        let mut power = q;
+        while power * q <= bounds.b1 {
+            power *= q;
+        }
+        x = x.clone().pow_mod(&Integer::from(power), &modulus).unwrap();
+        keep_going()
+    });
+    if !finished {
+        return None;
+    }
+    let mut minus_one = x.clone();
+    minus_one -= 1;
+    if let Some(factor) = proper_factor(minus_one.gcd(&modulus), &modulus) {
+        return Some(Pm1::Factor(factor));
+    }
+
+    // Stage 2: y = x^q for each prime q in (B1, B2], stepping from one prime to
+    // the next with x^gap; all the (y - 1) are multiplied up for a single gcd.
+    // Stage 2 starts above 2, so every gap is even.
+    let mut steps: Vec<Integer> = vec![];
+    let mut y: Option<(u64, Integer)> = None;
+    let mut product = Integer::from(1);
+    let finished = each_prime(bounds.b1.max(2) + 1, bounds.b2, &mut |q| {
+        let next = match y.take() {
+            None => x.clone().pow_mod(&Integer::from(q), &modulus).unwrap(),
+            Some((previous, mut value)) => {
+                let half_gap = ((q - previous) / 2) as usize;
+                while steps.len() < half_gap {
+                    let mut step = steps.last().cloned().unwrap_or_else(|| Integer::from(1));
+                    step *= &x;
+                    step *= &x;
+                    step %= &modulus;
+                    steps.push(step);
+                }
+                value *= &steps[half_gap - 1];
+                value %= &modulus;
+                value
+            }
+        };
+        let mut term = next.clone();
+        term -= 1;
+        product *= &term;
+        product %= &modulus;
+        y = Some((q, next));
+        keep_going()
+    });
+    if !finished {
+        return None;
+    }
+    match proper_factor(product.gcd(&modulus), &modulus) {
+        Some(factor) => Some(Pm1::Factor(factor)),
+        None => Some(Pm1::NoFactor),
+    }
+}
+
+// Dickman's rho: the chance that a random number x has no prime factor above x^(1/u).
+// Tabulated from rho(u) = 1 on [0, 1] and u rho'(u) = -rho(u - 1).
+struct Rho(Vec<f64>);
+
+const RHO_STEPS: usize = 64;
+const RHO_MAX: usize = 30;
+
+impl Rho {
+    fn new() -> Rho {
+        let h = 1.0 / RHO_STEPS as f64;
+        let mut table = vec![1.0; RHO_STEPS * RHO_MAX + 1];
+        for i in RHO_STEPS + 1..table.len() {
+            let slope = |j: usize| table[j - RHO_STEPS] / (j as f64 * h);
+            table[i] = table[i - 1] - h * (slope(i) + slope(i - 1)) / 2.0;
+        }
+        Rho(table)
+    }
+
+    fn at(&self, u: f64) -> f64 {
+        if u <= 1.0 {
+            return 1.0;
+        }
+        let position = u * RHO_STEPS as f64;
+        let i = position as usize;
+        if i + 1 >= self.0.len() {
+            return 0.0;
+        }
+        let t = position - i as f64;
+        (self.0[i] * (1.0 - t) + self.0[i + 1] * t).max(0.0)
+    }
+}
+
+// The chance that P-1 to `bounds` finds a factor of M_p, given that trial
+// factoring found none below 2^depth. Heuristically M_p has a factor of b bits
+// with probability 1/b, and P-1 finds it when k = (q - 1) / 2p is smooth enough.
+fn success_chance(rho: &Rho, prime: u32, depth: u32, bounds: Bounds) -> f64 {
+    const STAGE2_STEPS: usize = 16;
+    let ln_b1 = (bounds.b1 as f64).ln();
+    let ln_b2 = (bounds.b2.max(bounds.b1) as f64).ln();
+    let ln_2p = (2.0 * prime as f64).ln();
+    // Factors are 2kp + 1, so none has fewer bits than 2p + 1
+    let first = depth.max(64 - (2 * prime as u64 + 1).leading_zeros());
+    let mut found = 0.0;
+    for bits in first + 1..(prime / 2).min(first + 200) {
+        let ln_k = bits as f64 * std::f64::consts::LN_2 - ln_2p;
+        let mut smooth = rho.at(ln_k / ln_b1);
+        // Stage 2: the largest prime of k is r in (B1, B2] and the rest is B1-smooth
+        let dy = (ln_b2 - ln_b1) / STAGE2_STEPS as f64;
+        for step in 0..STAGE2_STEPS {
+            let ln_r = ln_b1 + (step as f64 + 0.5) * dy;
+            if ln_r < ln_k {
+                smooth += rho.at((ln_k - ln_r) / ln_b1) * dy / ln_r;
+            }
+        }
+        found += smooth.min(1.0) / bits as f64;
+    }
+    // That is how many factors P-1 finds on average, at least one with this chance
+    1.0 - (-found).exp()
+}
+
+// In squarings mod M_p, the unit of an LL iteration: stage 1 takes about
+// log2(lcm(1..B1)) = B1 / ln 2 of them, stage 2 two multiplications per prime
+fn cost(bounds: Bounds) -> f64 {
+    let primes_up_to = |n: f64| n / n.ln();
+    bounds.b1 as f64 / std::f64::consts::LN_2
+        + 2.0 * (primes_up_to(bounds.b2 as f64) - primes_up_to(bounds.b1 as f64))
+}
+
+// The bounds that save the most work on average: a factor saves the p - 2
+// iterations of the LL test, while P-1 costs its iterations whether it finds
+// one or not. None when no bounds are worth it, as for small exponents.
+pub fn choose_bounds(prime: u32, depth: u32) -> Option<Bounds> {
+    let rho = Rho::new();
+    let ll_cost = prime as f64;
+    let mut best: Option<(f64, Bounds)> = None;
+    let mut b1 = 1000;
+    while cost(Bounds { b1, b2: b1 }) < ll_cost {
+        for &ratio in &[1, 10, 20, 50, 100] {
+            let bounds = Bounds { b1, b2: b1 * ratio };
+            let saving = success_chance(&rho, prime, depth, bounds) * ll_cost - cost(bounds);
+            if saving > best.map_or(0.0, |(saving, _)| saving) {
+                best = Some((saving, bounds));
+            }
+        }
+        b1 *= 2;
+    }
+    best.map(|(_, bounds)| bounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(prime: u32, b1: u64, b2: u64) -> Pm1 {
+        p_minus_1(prime, Bounds { b1, b2 }, &mut || true).unwrap()
+    }
+
+    #[test]
+    fn finds_smooth_factors() {
+        // 193707721 = 2 * 67 * 2^2 * 3^3 * 5 * 2677 + 1
+        assert_eq!(run(67, 3000, 3000), Pm1::Factor(Integer::from(193707721)));
+        assert_eq!(run(67, 30, 3000), Pm1::Factor(Integer::from(193707721)));
+        assert_eq!(run(67, 30, 2000), Pm1::NoFactor);
+        // 7432339208719 = 2 * 101 * 3 * 44029 * 278557 + 1
+        assert_eq!(run(101, 44100, 278600), Pm1::Factor(Integer::from(7432339208719u64)));
+        assert_eq!(run(89, 1000, 20000), Pm1::NoFactor);
+        assert_eq!(p_minus_1(67, Bounds { b1: 3000, b2: 3000 }, &mut || false), None);
+    }
+
+    #[test]
+    fn primes_and_bounds() {
+        let mut primes = vec![];
+        each_prime(90, 130, &mut |q| {
+            primes.push(q);
+            true
+        });
+        assert_eq!(primes, vec![97, 101, 103, 107, 109, 113, 127]);
+
+        let rho = Rho::new();
+        assert!((rho.at(2.0) - (1.0 - 2f64.ln())).abs() < 1e-4);
+        assert!((rho.at(3.0) - 0.0486).abs() < 1e-3);
+
+        // Not worth it for small exponents, worth it for GIMPS-sized ones
+        assert_eq!(choose_bounds(10007, 34), None);
+        let bounds = choose_bounds(100_000_007, 74).unwrap();
+        assert!(bounds.b1 >= 100_000 && bounds.b2 > bounds.b1, "{:?}", bounds);
+
+        // Even with no trial factoring at all
+        for &prime in &[67, 1279, 10007, 100_000_007] {
+            for &(b1, b2) in &[(1000, 1000), (1000, 100_000), (1_000_000, 100_000_000)] {
+                let chance = success_chance(&rho, prime, 0, Bounds { b1, b2 });
+                assert!((0.0..=1.0).contains(&chance), "M{} to {}, {}: {}", prime, b1, b2, chance);
+            }
+        }
+    }
+}
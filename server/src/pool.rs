@@ -0,0 +1,122 @@
+use std::net::TcpStream;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Counters shown on the stats page
+
+#[derive(Default)]
+pub struct ConnectionStats {
+    active: AtomicUsize,
+    rejected: AtomicUsize,
+    streams: AtomicUsize,
+}
+
+impl ConnectionStats {
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    pub fn rejected(&self) -> usize {
+        self.rejected.load(Ordering::SeqCst)
+    }
+
+    pub fn streams(&self) -> usize {
+        self.streams.load(Ordering::SeqCst)
+    }
+
+    pub fn record_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // Long-lived connections (/events, /ws) run outside the pool but are capped too
+    pub fn try_start_stream(&self, limit: usize) -> bool {
+        self.streams
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                if n < limit {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+
+    pub fn end_stream(&self) {
+        self.streams.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// A fixed number of threads serving connections from a bounded queue.
+// When every thread is busy and the queue is full, the connection is handed back
+// to the caller so it can be turned away.
+
+pub struct Pool {
+    queue: SyncSender<TcpStream>,
+}
+
+impl Pool {
+    pub fn new<F>(size: usize, queue_depth: usize, stats: Arc<ConnectionStats>, handler: F) -> Pool
+    where
+        F: Fn(TcpStream) + Send + Sync + 'static,
+    {
+        let (queue, recv) = sync_channel(queue_depth);
+        let recv = Arc::new(Mutex::new(recv));
+        let handler = Arc::new(handler);
+
+        for _ in 0..size.max(1) {
+            let recv = Arc::clone(&recv);
+            let stats = Arc::clone(&stats);
+            let handler = Arc::clone(&handler);
+            thread::spawn(move || pool_worker(&recv, &stats, &*handler));
+        }
+
+        Pool { queue }
+    }
+
+    pub fn try_dispatch(&self, stream: TcpStream) -> Result<(), TcpStream> {
+        match self.queue.try_send(stream) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(stream)) | Err(TrySendError::Disconnected(stream)) => {
+                Err(stream)
+            }
+        }
+    }
+}
+
+fn pool_worker(
+    recv: &Mutex<Receiver<TcpStream>>,
+    stats: &ConnectionStats,
+    handler: &(dyn Fn(TcpStream) + Send + Sync),
+) {
+    loop {
+        // The lock is released as soon as a connection is taken off the queue
+        let stream = match recv.lock().unwrap().recv() {
+            Ok(stream) => stream,
+            Err(_) => return,
+        };
+
+        stats.active.fetch_add(1, Ordering::SeqCst);
+        // A panicking handler costs one connection, not a pool thread
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| handler(stream)));
+        stats.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_limit() {
+        let stats = ConnectionStats::default();
+        assert!(stats.try_start_stream(2));
+        assert!(stats.try_start_stream(2));
+        assert!(!stats.try_start_stream(2));
+        stats.end_stream();
+        assert!(stats.try_start_stream(2));
+        assert_eq!(stats.streams(), 2);
+    }
+}
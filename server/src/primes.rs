@@ -1,23 +1,122 @@
+use rand::Rng;
 use rug::Integer;
 
-fn prime_seq(mut n: u32, modulus: &Integer) -> Integer {
-    let mut m = Integer::from(4);
+use common::primes::Plain;
+pub use common::primes::{ErrorChecks, Outcome, Residue, Squarer, Stopped, Test};
 
-    while n > 1 {
-        m.square_mut();
-        m -= 2;
-        m %= modulus;
-        n -= 1;
+use crate::ibdwt::Ibdwt;
+
+// The tests themselves, their residues and error checks are in common::primes,
+// shared with rust-mersenne and rust-checker. What is the server's own is how
+// the squaring is done and the shift of a double-check.
+
+// A shift for a double-check; any but 0 makes it go through other numbers than the first test
+pub fn random_shift(prime: u32) -> u32 {
+    rand::thread_rng().gen_range(1..prime.max(2))
+}
+
+// What does the squaring in LL and PRP tests, chosen with --engine: rug's
+// Integer, or the floating point FFT of ibdwt.rs. Only Integer squarings are
+// split over idle threads, the FFT always runs on one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Engine {
+    Integer,
+    Ibdwt,
+}
+
+impl Engine {
+    pub fn parse(name: &str) -> Result<Engine, String> {
+        match name.to_lowercase().as_str() {
+            "integer" => Ok(Engine::Integer),
+            "ibdwt" => Ok(Engine::Ibdwt),
+            _ => Err(format!("unknown engine {}, expected integer or ibdwt", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Engine::Integer => "integer",
+            Engine::Ibdwt => "ibdwt",
+        }
+    }
+
+    fn squarer<'a>(
+        self,
+        prime: u32,
+        threads: &'a dyn Fn() -> usize,
+    ) -> Box<dyn Squarer<Integer> + 'a> {
+        match self {
+            Engine::Integer => Box::new(Plain::new(prime, threads)),
+            Engine::Ibdwt => Box::new(Ibdwt::new(prime)),
+        }
+    }
+
+    // `test` of M_p from `start` with this engine doing the squarings
+    pub fn run(
+        self,
+        test: Test,
+        prime: u32,
+        start: Residue,
+        threads: &dyn Fn() -> usize,
+        checks: &ErrorChecks,
+        safe_point: &mut dyn FnMut(&Residue, u32) -> bool,
+    ) -> Result<Outcome, Stopped> {
+        let mut squarer = self.squarer(prime, threads);
+        test.run(prime, start, &mut *squarer, checks, safe_point)
     }
-    m
 }
 
-pub fn is_mersenne_prime(prime: u32) -> bool {
-    let mut m = Integer::from(1) << prime;
-    m -= 1;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPONENTS: [u32; 15] = [3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 61, 67, 89, 107, 127];
+    const MERSENNE: [u32; 11] = [3, 5, 7, 13, 17, 19, 31, 61, 89, 107, 127];
+
+    // `test` of M_p from `start` on one thread
+    fn run(
+        engine: Engine,
+        test: Test,
+        p: u32,
+        start: Residue,
+        checks: &ErrorChecks,
+    ) -> Result<Outcome, Stopped> {
+        engine.run(test, p, start, &|| 1, checks, &mut |_, _| true)
+    }
+
+    fn is_mersenne_prime(p: u32) -> bool {
+        let checks = ErrorChecks::default();
+        run(Engine::Integer, Test::LucasLehmer, p, Residue::start(), &checks).unwrap().is_prime
+    }
 
-    let s = prime_seq(prime - 1, &m);
-    let is_prime = s == 0;
+    #[test]
+    fn mersenne_exponents_below_130() {
+        let found: Vec<u32> = EXPONENTS.iter().copied().filter(|&p| is_mersenne_prime(p)).collect();
+        assert_eq!(found, MERSENNE);
+    }
 
-    is_prime
+    #[test]
+    fn shifts_for_double_checks() {
+        assert!((1..5).contains(&random_shift(5)));
+        let checks = ErrorChecks::new(100);
+        let ll = |start| run(Engine::Integer, Test::LucasLehmer, 1279, start, &checks);
+        assert_eq!(ll(Residue::shifted_start(1279, random_shift(1279))), ll(Residue::start()));
+    }
+
+    #[test]
+    fn engines_agree() {
+        let checks = ErrorChecks::new(50);
+        for &test in &[Test::LucasLehmer, Test::Prp] {
+            for &p in &[3, 5, 11, 31, 89, 521, 1279, 2203] {
+                let ibdwt = run(Engine::Ibdwt, test, p, test.start(), &checks);
+                let integer = run(Engine::Integer, test, p, test.start(), &checks);
+                assert_eq!(ibdwt, integer, "{} of M{}", test.name(), p);
+            }
+        }
+        let shifted = Residue::shifted_start(1279, 300);
+        let outcome = run(Engine::Ibdwt, Test::LucasLehmer, 1279, shifted, &checks);
+        assert!(outcome.unwrap().is_prime);
+        assert_eq!(checks.failures(), 0);
+        assert_eq!(Engine::parse("IBDWT"), Ok(Engine::Ibdwt));
+    }
 }
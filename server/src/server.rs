@@ -1,52 +1,229 @@
-use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{self, BufRead, BufReader, Write};
 use std::net::{TcpListener, TcpStream};
-use std::path::Path;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, Mutex};
 use std::thread;
-
-const OK_FOUND: &'static str = "HTTP/1.1 200 Ok\r\n";
-const NOT_FOUND: &'static str = "HTTP/1.1 404 Not Found\r\n\r\n";
+use std::time::{Duration, Instant};
+
+use common::json;
+
+use crate::activity::Activity;
+use crate::assets;
+use crate::control::{Control, MAX_THREADS};
+use crate::costmodel::{self, CostModel};
+use crate::events::{Event, EventHub};
+use crate::http::{self, Request, RequestError, Response};
+use crate::jobs::{JobQueue, JobSpec, JobStatus};
+use crate::metrics::{self, Histogram};
+use crate::pool::{ConnectionStats, Pool};
+use crate::primes::{Engine, ErrorChecks, Test};
+use crate::schedule::Order;
+use crate::websocket;
+
+const EVENT_STREAM: &str = "HTTP/1.1 200 OK\r\n\
+    Content-Type: text/event-stream\r\n\
+    Cache-Control: no-cache\r\n\
+    Connection: keep-alive\r\n\r\n";
+
+// How often /events subscribers get a throughput snapshot
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5);
+
+// How long a keep-alive connection may wait for its next request. Much shorter
+// than the request timeout, as every idle connection holds a pool thread.
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// The write timeout of a 503 to a connection the pool has no room for. It is
+// written on the accept loop, which must not wait on a slow client.
+const REJECT_TIMEOUT: Duration = Duration::from_millis(100);
 
 #[derive(Clone, Copy)]
-#[allow(non_snake_case)]
 pub struct ServerPayload {
-    pub prime_count: u32,
-    pub biggest: u32,
     pub start: std::time::Instant,
-    pub N_THREADS: usize,
     pub lower_bound: u32,
     pub upper_bound: u32,
+    pub order: Order,
+    pub test: Test,
+    pub engine: Engine,
 }
 
-fn generate_json() -> String {
-    let content_type = "Content-Type: application/json\r\n";
+// Connection handling limits, see `args` for the command line flags
+
+#[derive(Clone)]
+pub struct ServerConfig {
+    // Threads answering ordinary requests
+    pub pool_size: usize,
+    // Accepted connections allowed to wait for a free pool thread
+    pub queue_depth: usize,
+    // Open /events and /ws connections, each of which has a thread of its own
+    pub max_streams: usize,
+    // Applies to every read and write; also how long idle keep-alive connections live
+    pub timeout: Duration,
+    // Dashboard files found here take precedence over the embedded copies
+    pub www_dir: Option<PathBuf>,
+}
 
-    let json = String::from("[]");
+// Everything a connection handler needs to answer a request
+
+#[derive(Clone)]
+pub struct ServerState {
+    pub found_mersennes: Arc<Mutex<Vec<u32>>>,
+    pub checked_count: Arc<Mutex<Vec<u32>>>,
+    pub events: Arc<EventHub>,
+    pub control: Arc<Control>,
+    pub durations: Arc<Histogram>,
+    pub activity: Arc<Activity>,
+    pub checks: Arc<ErrorChecks>,
+    pub costs: Arc<CostModel>,
+    pub jobs: Arc<JobQueue>,
+    pub connections: Arc<ConnectionStats>,
+    pub config: ServerConfig,
+    pub payload: ServerPayload,
+}
 
-    format!("{}\r\n{}", content_type, json)
+// What the workers are doing: "cancelling" wins over "paused" because a
+// cancel releases paused workers, and "idle" means the job queue is empty
+fn run_state(state: &ServerState) -> (&'static str, Option<u32>) {
+    let current = state.jobs.current();
+    let name = match &current {
+        Some(job) if job.status == JobStatus::Cancelling => "cancelling",
+        _ if state.control.is_paused() => "paused",
+        Some(_) => "running",
+        None => "idle",
+    };
+    (name, current.map(|job| job.id))
 }
 
-fn generate_stats_html(
-    primes: Arc<Mutex<Vec<u32>>>,
-    checked_count: Arc<Mutex<Vec<u32>>>,
-    payload: ServerPayload,
-) -> String {
+// Bump whenever a field is renamed or removed from the /json document
+const JSON_VERSION: u32 = 1;
+
+// The /json document, also pushed as telemetry to WebSocket clients
+fn stats_json(state: &ServerState) -> String {
+    let ServerState {
+        found_mersennes: primes,
+        checked_count,
+        control,
+        activity,
+        checks,
+        costs,
+        jobs,
+        connections,
+        payload,
+        ..
+    } = state;
+    let ServerPayload {
+        start,
+        lower_bound,
+        upper_bound,
+        order,
+        test,
+        engine,
+    } = *payload;
+
+    let prime_count = jobs.total_exponents();
+    let biggest = jobs.biggest_exponent();
+    let (run_state, current_job) = run_state(state);
+
+    let mut found = primes.lock().unwrap().clone();
+    found.sort();
+
+    let counts = checked_count.lock().unwrap().clone();
+    let sum: u64 = counts.iter().map(|&n| n as u64).sum();
+
+    let percentage = 100f64 * (sum as f64) / (prime_count as f64);
+    let elapsed = start.elapsed();
+    let elapsed_per_prime = elapsed.as_millis() as f64 / sum as f64;
+    let primes_per_second = sum as f64 / elapsed.as_secs_f64();
+    let progress = costs.progress(jobs, control.threads());
+    let cost_model = match costs.coefficients() {
+        Some((a, b)) => format!("{{\"a\": {:e}, \"b\": {}}}", a, json::f64(b)),
+        None => String::from("null"),
+    };
+
+    let mut fields = vec![];
+    fields.push(format!("\"version\": {}", JSON_VERSION));
+    fields.push(format!("\"threads\": {}", control.threads()));
+    fields.push(format!("\"order\": {}", json::string(order.name())));
+    fields.push(format!("\"test\": {}", json::string(test.name())));
+    fields.push(format!("\"engine\": {}", json::string(engine.name())));
+    fields.push(format!("\"state\": {}", json::string(run_state)));
+    fields.push(format!("\"paused\": {}", control.is_paused()));
+    fields.push(format!(
+        "\"job\": {}",
+        current_job.map_or(String::from("null"), |id| id.to_string())
+    ));
+    fields.push(format!("\"lower_bound\": {}", lower_bound));
+    fields.push(format!("\"upper_bound\": {}", upper_bound));
+    fields.push(format!("\"prime_count\": {}", prime_count));
+    fields.push(format!("\"biggest\": {}", biggest));
+    fields.push(format!("\"checked\": {}", sum));
+    fields.push(format!("\"checked_per_thread\": {}", json::array(&counts)));
+    let running: Vec<String> = activity.snapshot().iter().map(|s| s.to_json()).collect();
+    fields.push(format!("\"running\": [{}]", running.join(", ")));
+    fields.push(format!("\"percent_done\": {}", json::f64(percentage)));
+    let work_done = progress.fraction.map_or(f64::NAN, |fraction| 100.0 * fraction);
+    fields.push(format!("\"work_done_percent\": {}", json::f64(work_done)));
+    fields.push(format!("\"eta_secs\": {}", json::f64(progress.eta_secs.unwrap_or(f64::NAN))));
+    fields.push(format!("\"cost_model\": {}", cost_model));
+    fields.push(format!("\"elapsed_secs\": {}", json::f64(elapsed.as_secs_f64())));
+    fields.push(format!("\"ms_per_prime\": {}", json::f64(elapsed_per_prime)));
+    fields.push(format!("\"primes_per_second\": {}", json::f64(primes_per_second)));
+    fields.push(format!("\"check_failures\": {}", checks.failures()));
+    fields.push(format!("\"found_count\": {}", found.len()));
+    fields.push(format!("\"found\": {}", json::array(&found)));
+    fields.push(format!(
+        "\"connections\": {{\"active\": {}, \"rejected\": {}, \"streams\": {}}}",
+        connections.active(),
+        connections.rejected(),
+        connections.streams()
+    ));
+
+    format!("{{\n  {}\n}}\n", fields.join(",\n  "))
+}
+
+fn generate_stats_html(state: &ServerState) -> String {
+    let ServerState {
+        found_mersennes: primes,
+        checked_count,
+        control,
+        activity,
+        checks,
+        costs,
+        jobs,
+        connections,
+        config,
+        payload,
+        ..
+    } = state;
+
     let nums = primes.lock().unwrap();
     let num = nums.len();
     let ServerPayload {
-        biggest,
-        prime_count,
         start,
-        N_THREADS,
         lower_bound,
         upper_bound,
-    } = payload;
+        order,
+        test,
+        engine,
+    } = *payload;
 
-    let mut out = format!(
-        "{}\r\nActive threads: {}\nBiggest prime to check: {}\n",
-        OK_FOUND, N_THREADS, biggest
-    );
+    let prime_count = jobs.total_exponents();
+    let biggest = jobs.biggest_exponent();
+
+    let mut out = match run_state(state) {
+        (run_state, Some(id)) => format!("State: {} (job #{})\n", run_state, id),
+        (run_state, None) => format!("State: {}\n", run_state),
+    };
+
+    out.push_str(&format!(
+        "Active threads: {} ({}, {}, {})\nBiggest prime to check: {}\n",
+        control.threads(),
+        test.name(),
+        engine.name(),
+        order.name(),
+        biggest
+    ));
 
     out.push_str(&format!("Lower bound: {}\nUpper bound: {}\n", lower_bound, upper_bound));
 
@@ -62,15 +239,35 @@ fn generate_stats_html(
 
     let percentage = 100f64 * (sum as f64) / (prime_count as f64);
     out.push_str(&format!("{:.2}% done.\n", percentage));
+    // By count the small exponents make this look further along than it is
+    let progress = costs.progress(jobs, control.threads());
+    out.push_str(&format!("By work: {}\n", progress.describe()));
 
     out.push_str(&format!("Total done: {}\n", sum));
 
+    // Threads dropped by lowering the worker count keep their tally
     for (i, n) in counts.iter().enumerate() {
-        out.push_str(&format!(" Thread {} did: {}\n", i + 1, n));
+        let stopped = if i < control.threads() { "" } else { " (stopped)" };
+        let now = match activity.current(i) {
+            Some(snapshot) => format!(", now {}", snapshot.describe()),
+            None => String::new(),
+        };
+        out.push_str(&format!(" Thread {} did: {}{}{}\n", i + 1, n, stopped, now));
     }
 
     out.push_str(&format!("\n"));
 
+    out.push_str(&format!(
+        "Connections: {} active of {}, {} rejected\nStreams: {} open of {}\n",
+        connections.active(),
+        config.pool_size,
+        connections.rejected(),
+        connections.streams(),
+        config.max_streams
+    ));
+
+    out.push_str(&format!("\n"));
+
     let elapsed = start.elapsed();
     out.push_str(&format!("Time elapsed: {:.2?}\n", elapsed));
     let elapsed_per_prime = elapsed.as_millis() as f64 / sum as f64;
@@ -80,6 +277,11 @@ fn generate_stats_html(
     ));
     let primes_per_second = sum as f64 / elapsed.as_secs_f64();
     out.push_str(&format!("Primes per second: {:.2?}\n", primes_per_second));
+    if let Some(eta) = progress.eta_secs {
+        out.push_str(&format!("Projected finish in: {}\n", costmodel::format_secs(Some(eta))));
+    }
+    // Each one was caught and rolled back, but many of them point at bad hardware
+    out.push_str(&format!("Failed error checks: {}\n", checks.failures()));
 
     out.push_str(&format!("\n"));
 
@@ -93,52 +295,348 @@ fn generate_stats_html(
     out
 }
 
-fn serve_www_file(path: &str) -> String {
-    let base = Path::new("./src/www");
-    let mut file = File::open(base.join(path)).unwrap();
-    let mut s = String::new();
-    file.read_to_string(&mut s).unwrap();
-    s
+fn generate_progress_event(state: &ServerState) -> Event {
+    let ServerState {
+        found_mersennes: primes,
+        checked_count,
+        jobs,
+        payload,
+        ..
+    } = state;
+
+    let found_count = primes.lock().unwrap().len();
+    let sum: u64 = checked_count.lock().unwrap().iter().map(|&n| n as u64).sum();
+
+    let prime_count = jobs.total_exponents();
+    let percentage = 100f64 * (sum as f64) / (prime_count as f64);
+    let elapsed = payload.start.elapsed();
+    let primes_per_second = sum as f64 / elapsed.as_secs_f64();
+
+    Event {
+        name: "progress",
+        data: format!(
+            "{{\"checked\": {}, \"prime_count\": {}, \"percent_done\": {}, \
+             \"elapsed_secs\": {}, \"primes_per_second\": {}, \"found_count\": {}}}",
+            sum,
+            prime_count,
+            json::f64(percentage),
+            json::f64(elapsed.as_secs_f64()),
+            json::f64(primes_per_second),
+            found_count
+        ),
+    }
 }
 
-pub fn handle_client(
-    mut stream: TcpStream,
-    primes: Arc<Mutex<Vec<u32>>>,
-    checked_count: Arc<Mutex<Vec<u32>>>,
-    payload: ServerPayload,
-) {
-    // println!("==================");
-    // println!("Connection incoming!");
+// Keeps the connection open and forwards every published event to the client,
+// with a progress snapshot at least every SNAPSHOT_INTERVAL.
+// Returns once the client goes away.
+fn stream_events(mut stream: TcpStream, state: &ServerState) {
+    let recv = state.events.subscribe();
+
+    let snapshot = generate_progress_event(state);
+    let mut last_snapshot = Instant::now();
+    let greeting = format!("{}{}", EVENT_STREAM, snapshot.to_sse());
+    if stream.write_all(greeting.as_bytes()).is_err() {
+        return;
+    }
 
-    let mut buffer = [0; 1024];
+    loop {
+        let timeout = SNAPSHOT_INTERVAL
+            .checked_sub(last_snapshot.elapsed())
+            .unwrap_or_default();
+
+        let mut out = match recv.recv_timeout(timeout) {
+            Ok(event) => event.to_sse(),
+            Err(RecvTimeoutError::Timeout) => String::new(),
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+
+        if last_snapshot.elapsed() >= SNAPSHOT_INTERVAL {
+            let snapshot = generate_progress_event(state);
+            out.push_str(&snapshot.to_sse());
+            last_snapshot = Instant::now();
+        }
+
+        if stream.write_all(out.as_bytes()).and_then(|_| stream.flush()).is_err() {
+            return;
+        }
+    }
+}
 
-    stream.read(&mut buffer).unwrap();
+fn upgrade_websocket(mut stream: TcpStream, client_key: &str, state: &ServerState) {
+    let handshake = websocket::handshake_response(client_key);
+    if stream.write_all(handshake.as_bytes()).is_err() {
+        return;
+    }
 
-    let request = String::from_utf8_lossy(&buffer[..]);
-    // print!("Request: {}", request);
+    let telemetry = || stats_json(state);
+    websocket::run_session(
+        stream,
+        &state.control,
+        &state.jobs,
+        SNAPSHOT_INTERVAL,
+        telemetry,
+    );
+}
 
-    let header = request.lines().nth(0).unwrap();
-    // println!("First line: {}", header);
+fn serve_asset(request: &Request, state: &ServerState) -> Response {
+    let asset = match assets::lookup(&request.path, state.config.www_dir.as_deref()) {
+        Some(asset) => asset,
+        None => return Response::text(404, "Not Found"),
+    };
 
-    let resource = if String::from(header).starts_with("GET") {
-        &header.split(' ').nth(1).unwrap()[1..]
+    let last_modified = asset.last_modified.map(assets::http_date);
+    let etag_matches = request
+        .header("If-None-Match")
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == asset.etag));
+    // Browsers echo our own Last-Modified back, so an exact match is enough
+    let unmodified_since = request.header("If-None-Match").is_none()
+        && last_modified.is_some()
+        && request.header("If-Modified-Since") == last_modified.as_deref();
+
+    let mut response = if etag_matches || unmodified_since {
+        Response {
+            status: 304,
+            headers: vec![],
+            body: vec![],
+        }
     } else {
-        ""
+        Response::new(200, asset.content_type, asset.contents)
     };
-    // println!("PATH: {}", resource);
-
-    let response = match resource {
-        "" => format!("{}\r\n{}", OK_FOUND, serve_www_file("index.html")),
-        "main.js" => format!("{}\r\n{}", OK_FOUND, serve_www_file("main.js")),
-        "count" => generate_stats_html(primes, checked_count, payload),
-        "json" => format!("{}{}", OK_FOUND, generate_json()),
-        _ => NOT_FOUND.to_owned(),
+    response = response
+        .with_header("ETag", &asset.etag)
+        .with_header("Cache-Control", "no-cache");
+    if let Some(date) = last_modified {
+        response = response.with_header("Last-Modified", &date);
+    }
+    response
+}
+
+//============================================================================
+// REQUEST HANDLING
+
+// What to do with a parsed request: answer it, or hand the connection over
+// to one of the long-lived protocols
+enum Reply {
+    Response(Response),
+    Events,
+    WebSocket(String),
+}
+
+//============================================================================
+// JOBS API
+
+fn submit_job(request: &Request, state: &ServerState) -> Response {
+    let body = match std::str::from_utf8(&request.body) {
+        Ok(body) => body,
+        Err(_) => return Response::text(400, "Request body is not valid UTF-8"),
+    };
+    let spec = match JobSpec::from_json(body) {
+        Ok(spec) => spec,
+        Err(message) => return Response::text(400, &message),
+    };
+    if let Err(message) = spec.validate() {
+        return Response::text(400, &message);
+    }
+
+    let id = state.jobs.submit(spec);
+    let job = state.jobs.get(id).unwrap();
+    Response::json(201, format!("{}\n", job.to_json())).with_header("Location", &format!("/jobs/{}", id))
+}
+
+fn list_jobs(state: &ServerState) -> Response {
+    let jobs: Vec<String> = state.jobs.all().iter().map(|job| job.to_json()).collect();
+    Response::json(200, format!("[\n  {}\n]\n", jobs.join(",\n  ")))
+}
+
+fn cancel_job(state: &ServerState, id: u32) -> Response {
+    if state.jobs.get(id).is_none() {
+        return Response::text(404, "No such job");
+    }
+    match state.jobs.cancel(id) {
+        // 202 as a running job only stops at the workers' next safe point
+        Ok(_) => Response::json(202, format!("{}\n", state.jobs.get(id).unwrap().to_json())),
+        Err(message) => Response::text(409, &message),
+    }
+}
+
+// POST /jobs, GET /jobs, GET /jobs/{id} and POST /jobs/{id}/cancel
+fn jobs_api(request: &Request, state: &ServerState, is_read: bool) -> Response {
+    if request.path == "/jobs" {
+        return match request.method.as_str() {
+            "POST" => submit_job(request, state),
+            _ if is_read => list_jobs(state),
+            _ => Response::text(405, "Method Not Allowed").with_header("Allow", "GET, HEAD, POST"),
+        };
+    }
+
+    let rest = request.path.trim_start_matches("/jobs/");
+    if let Some(id) = rest.strip_suffix("/cancel") {
+        return match (request.method.as_str(), id.parse()) {
+            ("POST", Ok(id)) => cancel_job(state, id),
+            ("POST", Err(_)) => Response::text(404, "No such job"),
+            _ => Response::text(405, "Method Not Allowed").with_header("Allow", "POST"),
+        };
+    }
+
+    if !is_read {
+        return Response::text(405, "Method Not Allowed").with_header("Allow", "GET, HEAD");
+    }
+    match rest.parse().ok().and_then(|id| state.jobs.get(id)) {
+        Some(job) => Response::json(200, format!("{}\n", job.to_json())),
+        None => Response::text(404, "No such job"),
+    }
+}
+
+// The body of POST /control/threads: {"threads": n}
+fn parse_thread_count(body: &[u8]) -> Result<usize, String> {
+    let body = std::str::from_utf8(body).map_err(|_| "Request body is not valid UTF-8")?;
+    let value = json::parse(body)?;
+    let count = value.get("threads").and_then(json::Value::as_u32);
+    match count {
+        Some(count) if count >= 1 && count as usize <= MAX_THREADS => Ok(count as usize),
+        _ => Err(format!("\"threads\" must be between 1 and {}", MAX_THREADS)),
+    }
+}
+
+// POST /control/pause, /control/resume and /control/threads; all answer with the /json document
+fn control_api(request: &Request, state: &ServerState) -> Response {
+    if request.method != "POST" {
+        return Response::text(405, "Method Not Allowed").with_header("Allow", "POST");
+    }
+    match request.path.as_str() {
+        "/control/pause" => state.control.pause(),
+        "/control/resume" => state.control.resume(),
+        "/control/threads" => match parse_thread_count(&request.body) {
+            Ok(count) => {
+                state.control.set_threads(count);
+            }
+            Err(message) => return Response::text(400, &message),
+        },
+        _ => return Response::text(404, "Not Found"),
+    }
+    Response::json(200, stats_json(state))
+}
+
+fn route(request: &Request, state: &ServerState) -> Reply {
+    let is_read = request.method == "GET" || request.method == "HEAD";
+
+    if request.path == "/jobs" || request.path.starts_with("/jobs/") {
+        return Reply::Response(jobs_api(request, state, is_read));
+    }
+    if request.path.starts_with("/control/") {
+        return Reply::Response(control_api(request, state));
+    }
+
+    let response = match request.path.as_str() {
+        _ if !is_read => {
+            Response::text(405, "Method Not Allowed").with_header("Allow", "GET, HEAD")
+        }
+        "/count" => Response::text(200, &generate_stats_html(state)),
+        "/json" => Response::json(200, stats_json(state)),
+        "/metrics" => Response::new(
+            200,
+            "text/plain; version=0.0.4; charset=utf-8",
+            metrics::generate_metrics(state).into_bytes(),
+        ),
+        "/events" => return Reply::Events,
+        "/ws" => match request.header("Sec-WebSocket-Key") {
+            Some(key) if is_websocket_upgrade(request) => return Reply::WebSocket(key.to_owned()),
+            _ => Response::text(400, "Expected a WebSocket upgrade"),
+        },
+        _ => serve_asset(request, state),
     };
+    Reply::Response(response)
+}
+
+fn is_websocket_upgrade(request: &Request) -> bool {
+    let upgrade = request.header("Upgrade").unwrap_or("");
+    let connection = request.header("Connection").unwrap_or("").to_ascii_lowercase();
+    request.method == "GET"
+        && upgrade.eq_ignore_ascii_case("websocket")
+        && connection.split(',').any(|token| token.trim() == "upgrade")
+}
+
+// Serves requests from one connection until the client closes it, asks us to,
+// or stays idle for longer than KEEP_ALIVE_TIMEOUT. Once a request starts
+// arriving it gets the configured timeout.
+// Errors only ever end this connection, they never escape as panics.
+pub fn handle_client(stream: TcpStream, state: &ServerState) {
+    let _ = serve_connection(stream, state);
+}
 
-    stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
+fn serve_connection(mut stream: TcpStream, state: &ServerState) -> io::Result<()> {
+    stream.set_write_timeout(Some(state.config.timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    loop {
+        stream.set_read_timeout(Some(KEEP_ALIVE_TIMEOUT.min(state.config.timeout)))?;
+        match reader.fill_buf() {
+            Ok([]) | Err(_) => return Ok(()),
+            Ok(_) => stream.set_read_timeout(Some(state.config.timeout))?,
+        }
+        let request = match http::read_request(&mut reader) {
+            Ok(request) => request,
+            Err(RequestError::Closed) | Err(RequestError::Io) => return Ok(()),
+            Err(err) => {
+                if let Some(response) = err.response() {
+                    response.write_to(&mut stream, false, false)?;
+                }
+                return Ok(());
+            }
+        };
+
+        // A panic while building the answer (e.g. a poisoned lock) becomes a 500
+        let reply = panic::catch_unwind(AssertUnwindSafe(|| route(&request, state)))
+            .unwrap_or_else(|_| Reply::Response(Response::text(500, "Internal Server Error")));
+
+        match reply {
+            Reply::Response(response) => {
+                let keep_alive = request.keep_alive();
+                let head_only = request.method == "HEAD";
+                response.write_to(&mut stream, head_only, keep_alive)?;
+                if !keep_alive {
+                    return Ok(());
+                }
+            }
+            Reply::Events => return start_stream(stream, state, stream_events),
+            Reply::WebSocket(key) => {
+                return start_stream(stream, state, move |stream, state| {
+                    upgrade_websocket(stream, &key, state)
+                });
+            }
+        }
+    }
+}
 
-    // println!("Closing connection!");
+// Moves a long-lived connection off the pool onto a thread of its own,
+// so a few open dashboards cannot starve ordinary requests
+fn start_stream<F>(mut stream: TcpStream, state: &ServerState, serve: F) -> io::Result<()>
+where
+    F: FnOnce(TcpStream, &ServerState) + Send + 'static,
+{
+    if !state.connections.try_start_stream(state.config.max_streams) {
+        state.connections.record_rejected();
+        let response = Response::text(503, "Too many open streams").with_header("Retry-After", "5");
+        return response.write_to(&mut stream, false, false);
+    }
+
+    // Streams only go quiet when nothing happens, which is not a reason to drop them
+    stream.set_read_timeout(None)?;
+
+    let state = state.clone();
+    thread::spawn(move || {
+        serve(stream, &state);
+        state.connections.end_stream();
+    });
+    Ok(())
+}
+
+fn reject_busy(mut stream: TcpStream, state: &ServerState) {
+    state.connections.record_rejected();
+    let _ = stream.set_write_timeout(Some(REJECT_TIMEOUT));
+    let response = Response::text(503, "Server busy").with_header("Retry-After", "1");
+    let _ = response.write_to(&mut stream, false, false);
 }
 
 //============================================================================
@@ -146,23 +644,38 @@ pub fn handle_client(
 
 const PORT: u32 = 8080;
 
-pub fn server_main(
-    found_mersennes: &Arc<Mutex<Vec<u32>>>,
-    checked_count: &Arc<Mutex<Vec<u32>>>,
-    payload: ServerPayload,
-) -> std::io::Result<()> {
+pub fn server_main(state: ServerState) -> std::io::Result<()> {
     println!("Server started");
 
     let ip = format!("0.0.0.0:{}", PORT);
     let listener = TcpListener::bind(ip)?;
 
-    // accept connections and process them serially
+    let ServerConfig {
+        pool_size,
+        queue_depth,
+        ..
+    } = state.config;
+    println!(
+        "Serving with {} connection threads, queue depth {}",
+        pool_size, queue_depth
+    );
+
+    let handler_state = state.clone();
+    let pool = Pool::new(
+        pool_size,
+        queue_depth,
+        Arc::clone(&state.connections),
+        move |stream| handle_client(stream, &handler_state),
+    );
+
     for stream in listener.incoming() {
-        let primes = Arc::clone(&found_mersennes);
-        let checked_count_copy = Arc::clone(&checked_count);
-        thread::spawn(move || {
-            handle_client(stream.unwrap(), primes, checked_count_copy, payload);
-        });
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        if let Err(stream) = pool.try_dispatch(stream) {
+            reject_busy(stream, &state);
+        }
     }
     Ok(())
 }
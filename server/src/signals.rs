@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::control::Control;
+use crate::jobs::JobQueue;
+
+// Signal handlers may only touch atomics, so they raise these flags and
+// a watcher thread does the actual work:
+//   SIGUSR1 toggles pause/resume, SIGUSR2 cancels the running job
+
+static TOGGLE_PAUSE: AtomicBool = AtomicBool::new(false);
+static CANCEL_JOB: AtomicBool = AtomicBool::new(false);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    match signal {
+        libc::SIGUSR1 => TOGGLE_PAUSE.store(true, Ordering::SeqCst),
+        libc::SIGUSR2 => CANCEL_JOB.store(true, Ordering::SeqCst),
+        _ => {}
+    }
+}
+
+#[cfg(unix)]
+fn install_handlers() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGUSR1, handler);
+        libc::signal(libc::SIGUSR2, handler);
+    }
+}
+
+// No SIGUSR1/2 elsewhere; HTTP and WebSocket controls still work
+#[cfg(not(unix))]
+fn install_handlers() {}
+
+pub fn spawn_watcher(control: Arc<Control>, jobs: Arc<JobQueue>) -> thread::JoinHandle<()> {
+    install_handlers();
+    thread::spawn(move || loop {
+        if TOGGLE_PAUSE.swap(false, Ordering::SeqCst) {
+            if control.is_paused() {
+                control.resume();
+                println!("Resumed by signal");
+            } else {
+                control.pause();
+                println!("Paused by signal");
+            }
+        }
+        if CANCEL_JOB.swap(false, Ordering::SeqCst) {
+            match jobs.current() {
+                Some(job) => match jobs.cancel(job.id) {
+                    Ok(_) => println!("Cancelling job #{} by signal", job.id),
+                    Err(message) => println!("{}", message),
+                },
+                None => println!("No running job to cancel"),
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    })
+}
@@ -0,0 +1,360 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use crate::control::{Command, Control};
+use crate::jobs::{JobQueue, JobSpec};
+
+// RFC 6455 constants
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+// Commands are a few bytes long, anything bigger is a misbehaving client
+const MAX_MESSAGE_LEN: u64 = 64 * 1024;
+
+//============================================================================
+// HANDSHAKE
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([
+                chunk[4 * i],
+                chunk[4 * i + 1],
+                chunk[4 * i + 2],
+                chunk[4 * i + 3],
+            ]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e].iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (i, word) in h.iter().enumerate() {
+        digest[4 * i..4 * i + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+pub fn accept_key(client_key: &str) -> String {
+    let mut input = String::from(client_key.trim());
+    input.push_str(ACCEPT_GUID);
+    base64(&sha1(input.as_bytes()))
+}
+
+pub fn handshake_response(client_key: &str) -> String {
+    format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(client_key)
+    )
+}
+
+//============================================================================
+// FRAMING
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+fn read_frame(stream: &mut impl Read) -> io::Result<Frame> {
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head)?;
+
+    let fin = head[0] & 0x80 != 0;
+    let opcode = head[0] & 0x0F;
+    let masked = head[1] & 0x80 != 0;
+
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut ext = [0u8; 2];
+            stream.read_exact(&mut ext)?;
+            u16::from_be_bytes(ext) as u64
+        }
+        127 => {
+            let mut ext = [0u8; 8];
+            stream.read_exact(&mut ext)?;
+            u64::from_be_bytes(ext)
+        }
+        n => n as u64,
+    };
+
+    if !masked {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "unmasked client frame"));
+    }
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+    }
+
+    let mut mask = [0u8; 4];
+    stream.read_exact(&mut mask)?;
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok(Frame {
+        fin,
+        opcode,
+        payload,
+    })
+}
+
+fn write_frame(stream: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut out = vec![0x80 | opcode];
+    match payload.len() {
+        n if n < 126 => out.push(n as u8),
+        n if n <= u16::MAX as usize => {
+            out.push(126);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        n => {
+            out.push(127);
+            out.extend_from_slice(&(n as u64).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(payload);
+    stream.write_all(&out)?;
+    stream.flush()
+}
+
+//============================================================================
+// SESSION
+
+// What the reader thread hands over to the writing side
+
+enum Incoming {
+    Text(String),
+    Ping(Vec<u8>),
+}
+
+fn reply(text: &str, ok: bool) -> String {
+    let key = if ok { "ok" } else { "error" };
+    format!("{{\"{}\": \"{}\"}}", key, text.replace('"', "'"))
+}
+
+fn run_command(
+    command: Command,
+    control: &Control,
+    jobs: &JobQueue,
+    telemetry: &dyn Fn() -> String,
+) -> String {
+    match command {
+        Command::Pause => {
+            control.pause();
+            reply("paused", true)
+        }
+        Command::Resume => {
+            control.resume();
+            reply("resumed", true)
+        }
+        Command::AddRange(lower, upper) => {
+            let spec = JobSpec::Range(lower, upper);
+            match spec.validate() {
+                Ok(()) => {
+                    let id = jobs.submit(spec);
+                    reply(&format!("queued [{},{}] as job #{}", lower, upper, id), true)
+                }
+                Err(message) => reply(&message, false),
+            }
+        }
+        Command::Threads(count) => {
+            let count = control.set_threads(count);
+            reply(&format!("running on {} threads", count), true)
+        }
+        Command::Cancel(id) => match jobs.cancel(id) {
+            Ok(status) => reply(&format!("job #{} {}", id, status.name()), true),
+            Err(message) => reply(&message, false),
+        },
+        Command::Stats => telemetry(),
+    }
+}
+
+// Serves one upgraded connection until either side closes it.
+// A reader thread parses frames; this thread runs the commands, writes the
+// replies and pushes `telemetry()` every `interval`. Whichever side stops first,
+// the socket is shut down so that the reader is not left blocked in a read.
+pub fn run_session<F>(
+    mut stream: TcpStream,
+    control: &Control,
+    jobs: &JobQueue,
+    interval: Duration,
+    telemetry: F,
+) where
+    F: Fn() -> String,
+{
+    let mut reader = match stream.try_clone() {
+        Ok(reader) => reader,
+        Err(_) => return,
+    };
+
+    let (send, recv) = channel();
+
+    let reader = thread::spawn(move || {
+        let mut message = vec![];
+        while let Ok(frame) = read_frame(&mut reader) {
+            let incoming = match frame.opcode {
+                OP_TEXT | OP_CONTINUATION => {
+                    message.extend_from_slice(&frame.payload);
+                    if message.len() as u64 > MAX_MESSAGE_LEN {
+                        break;
+                    }
+                    if !frame.fin {
+                        continue;
+                    }
+                    let text = String::from_utf8_lossy(&message).into_owned();
+                    message.clear();
+                    Incoming::Text(text)
+                }
+                OP_PING => Incoming::Ping(frame.payload),
+                OP_PONG => continue,
+                // Close, binary and unknown opcodes all end the session
+                _ => break,
+            };
+            if send.send(incoming).is_err() {
+                break;
+            }
+        }
+        // Dropping `send` tells the writing side the client is gone
+    });
+
+    write_until_closed(&mut stream, &recv, control, jobs, interval, &telemetry);
+    let _ = stream.shutdown(Shutdown::Both);
+    let _ = reader.join();
+}
+
+// The writing side of a session, until the client goes away or a write fails
+fn write_until_closed(
+    stream: &mut TcpStream,
+    recv: &Receiver<Incoming>,
+    control: &Control,
+    jobs: &JobQueue,
+    interval: Duration,
+    telemetry: &dyn Fn() -> String,
+) {
+    if write_frame(stream, OP_TEXT, telemetry().as_bytes()).is_err() {
+        return;
+    }
+
+    loop {
+        let result = match recv.recv_timeout(interval) {
+            Ok(Incoming::Text(text)) => {
+                let answer = match Command::parse(&text) {
+                    Ok(command) => run_command(command, control, jobs, telemetry),
+                    Err(message) => reply(&message, false),
+                };
+                write_frame(stream, OP_TEXT, answer.as_bytes())
+            }
+            Ok(Incoming::Ping(payload)) => write_frame(stream, OP_PONG, &payload),
+            Err(RecvTimeoutError::Timeout) => write_frame(stream, OP_TEXT, telemetry().as_bytes()),
+            Err(RecvTimeoutError::Disconnected) => {
+                let _ = write_frame(stream, OP_CLOSE, &[]);
+                return;
+            }
+        };
+        if result.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc_6455_accept_key() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+    }
+
+    #[test]
+    fn masked_frame_roundtrip() {
+        // "Hello" from RFC 6455 section 5.7
+        let bytes = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let frame = read_frame(&mut &bytes[..]).unwrap();
+        assert!(frame.fin);
+        assert_eq!(frame.opcode, OP_TEXT);
+        assert_eq!(frame.payload, b"Hello");
+
+        let mut out = vec![];
+        write_frame(&mut out, OP_TEXT, b"Hello").unwrap();
+        assert_eq!(out, [0x81, 0x05, b'H', b'e', b'l', b'l', b'o']);
+    }
+}
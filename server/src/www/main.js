@@ -31,4 +31,12 @@ const loadData2 = async () => {
 loadData();
 loadData2();
 
-setInterval(loadData2, 5000);
\ No newline at end of file
+// Refresh on pushed events instead of polling; fall back to polling
+// for browsers without EventSource
+if (window.EventSource) {
+    const events = new EventSource("events");
+    events.addEventListener("found", loadData2);
+    events.addEventListener("progress", loadData2);
+} else {
+    setInterval(loadData2, 5000);
+}
\ No newline at end of file